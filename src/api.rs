@@ -0,0 +1,272 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use libc::{c_int, EINVAL};
+use fuse::{FileAttr, FileType};
+use super::{introspect, snapshot, Inner, NewAttr, RamFS, SetAttr, Tree};
+use super::snapshot::{SNAPSHOT_DIR, SNAPSHOT_DIR_INO};
+
+// The umask new inodes of the path API are created under, there being no calling process to take it from
+const UMASK: u32 = 0o022;
+
+/* One entry of a directory listing, '.' and '..' are never part of it */
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    pub name: OsString, // exactly the bytes it was created with
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+impl Tree {
+    /* Walks the parent links up from the directory 'ino' to the root */
+    fn dir_path(&self, ino: u64) -> Option<PathBuf> {
+        let mut names = Vec::new();
+        let mut ino = ino;
+        while ino != 1 {
+            let inode = self.inodes.get(&ino)?;
+            if names.len() > self.inodes.len() {
+                return None; // The parent links go round in circles, the tree is broken
+            }
+            names.push(inode.name.as_os_str());
+            ino = inode.root;
+        }
+        let mut path = PathBuf::from("/");
+        path.extend(names.iter().rev());
+        Some(path)
+    }
+
+    /* Returns every path that leads to 'ino', in order. A directory has a single one, a file has one for
+     * each directory entry linking to it, so all of its hard links are found */
+    pub(crate) fn paths(&self, ino: u64) -> Vec<PathBuf> {
+        match self.get(ino) {
+            None => Vec::new(),
+            Some(inode) if inode.is_dir() => self.dir_path(ino).into_iter().collect(),
+            // A single link is the one the inode keeps its name and parent for, only hard links need a search
+            Some(inode) if inode.attr.nlink == 1 => self.dir_path(inode.root).map(|dir| dir.join(&inode.name)).into_iter().collect(),
+            Some(_) => {
+                let mut paths: Vec<PathBuf> = self.inodes.iter()
+                    .filter_map(|(dir, inode)| inode.entries().map(|entries| (*dir, entries)))
+                    .flat_map(|(dir, entries)| entries.iter().filter(|(_, child)| **child == ino).map(move |(name, _)| (dir, name)))
+                    .filter_map(|(dir, name)| self.dir_path(dir).map(|path| path.join(name)))
+                    .collect();
+                paths.sort();
+                paths
+            }
+        }
+    }
+}
+
+impl Inner {
+    /* Returns every path 'ino' can be reached by, including inodes in the hidden directories */
+    pub(crate) fn paths(&self, ino: u64) -> Vec<PathBuf> {
+        if ino == SNAPSHOT_DIR_INO {
+            return vec![Path::new("/").join(SNAPSHOT_DIR)];
+        }
+        if snapshot::owns(ino) {
+            return self.snapshot_paths(ino);
+        }
+        if introspect::owns(ino) {
+            return vec![self.introspect_path(ino)];
+        }
+        self.tree.paths(ino)
+    }
+
+    /* What an inode created through the path API with 'mode' starts out as: it belongs to the owner of the
+     * root directory, and 'mode' loses the bits the usual umask of 022 takes off */
+    pub(crate) fn new_attr(&self, mode: u32) -> NewAttr {
+        NewAttr { perm: (mode & 0o7777 & !UMASK) as u16, uid: self.config.root_uid, gid: self.config.root_gid }
+    }
+
+    /* Names an inode in log messages, by its first path or by its number if it has none */
+    pub(crate) fn display_path(&self, ino: u64) -> String {
+        match self.paths(ino).into_iter().next() {
+            Some(path) => path.display().to_string(),
+            None => format!("<inode {}>", ino),
+        }
+    }
+
+    /* Names the entry 'name' of the directory 'parent' in log messages */
+    pub(crate) fn entry_path(&self, parent: u64, name: &OsStr) -> String {
+        match self.paths(parent).into_iter().next() {
+            Some(dir) => dir.join(name).display().to_string(),
+            None => format!("<inode {}>/{}", parent, name.to_string_lossy()),
+        }
+    }
+
+    /* Walks 'path' down from the root directory and returns the inode it ends at.
+     * Relative paths are taken relative to the root, and '..' never climbs above it */
+    fn resolve(&self, path: &Path) -> Result<u64, c_int> {
+        let mut walked = vec![1];
+        for component in path.components() {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => {
+                    if walked.len() > 1 {
+                        walked.pop();
+                    }
+                }
+                Component::Normal(name) => {
+                    let ino = self.lookup(walked[walked.len() - 1], name)?.ino;
+                    walked.push(ino);
+                }
+                Component::Prefix(_) => return Err(EINVAL),
+            }
+        }
+        Ok(walked[walked.len() - 1])
+    }
+
+    /* Splits 'path' into the inode of the directory holding it and the name it has in there */
+    fn resolve_parent<'a>(&self, path: &'a Path) -> Result<(u64, &'a OsStr), c_int> {
+        let name = path.file_name().ok_or(EINVAL)?;
+        let parent = path.parent().ok_or(EINVAL)?;
+        Ok((self.resolve(parent)?, name))
+    }
+}
+
+// This is the same file-system the FUSE handlers serve, addressed by path instead of inode so it can
+// be used in-process, e.g. as an in-memory VFS or from tests, without mounting anything
+impl RamFS {
+    /* Returns the absolute paths of an inode, one for each hard link to it. Nothing comes back for an
+     * inode that does not exist */
+    pub fn paths(&self, ino: u64) -> Vec<PathBuf> {
+        self.lock().paths(ino)
+    }
+
+    /* Creates an empty regular file, an existing file is handed back untouched */
+    pub fn create<P: AsRef<Path>>(&self, path: P) -> io::Result<FileAttr> {
+        self.create_with_flags(path.as_ref(), 0)
+    }
+
+    /* Runs a change made through the path API on the locked state. Debug builds check the tree after it,
+     * the way they do after every FUSE operation */
+    pub(crate) fn change<T>(&self, op: &str, f: impl FnOnce(&mut Inner) -> Result<T, c_int>) -> io::Result<T> {
+        let mut fs = self.lock();
+        let result = f(&mut fs);
+        if cfg!(debug_assertions) {
+            fs.debug_check(op);
+        }
+        result.map_err(io::Error::from_raw_os_error)
+    }
+
+    /* Same as 'create', with O_EXCL and O_TRUNC in 'flags' working the way they do for open(2) */
+    pub(crate) fn create_with_flags(&self, path: &Path, flags: u32) -> io::Result<FileAttr> {
+        self.change("create", |fs| {
+            let (parent, name) = fs.resolve_parent(path)?;
+            let new = fs.new_attr(0o666);
+            fs.create(parent, name, flags, new)
+        })
+    }
+
+    /* Returns the whole contents of a file */
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<u8>> {
+        let fs = self.lock();
+        let ino = fs.resolve(path.as_ref()).map_err(io::Error::from_raw_os_error)?;
+        let mut contents = Vec::new();
+        loop {
+            // No handle, so an introspection file is rendered on every call: a short chunk is the end
+            let chunk = fs.read(ino, 0, contents.len() as i64, u32::MAX).map_err(io::Error::from_raw_os_error)?;
+            contents.extend_from_slice(&chunk);
+            if chunk.len() < u32::MAX as usize {
+                return Ok(contents);
+            }
+        }
+    }
+
+    /* Replaces the contents of a file, creating it first if it is not there (like std::fs::write) */
+    pub fn write<P: AsRef<Path>>(&self, path: P, contents: &[u8]) -> io::Result<()> {
+        self.change("write", |fs| {
+            let (parent, name) = fs.resolve_parent(path.as_ref())?;
+            let new = fs.new_attr(0o666);
+            let ino = fs.create(parent, name, 0, new)?.ino;
+            fs.setattr(ino, SetAttr { size: Some(0), ..SetAttr::default() })?;
+            // A write can stop short, at the largest file allowed, and the next one then fails with EFBIG
+            let mut offset = 0;
+            while (offset as usize) < contents.len() {
+                let chunk = &contents[offset as usize..];
+                let chunk = &chunk[..chunk.len().min(u32::MAX as usize)];
+                offset += fs.write(ino, offset, chunk)? as i64;
+            }
+            Ok(())
+        })
+    }
+
+    /* Creates a new, empty directory */
+    pub fn mkdir<P: AsRef<Path>>(&self, path: P) -> io::Result<FileAttr> {
+        self.change("mkdir", |fs| {
+            let (parent, name) = fs.resolve_parent(path.as_ref())?;
+            let new = fs.new_attr(0o777);
+            fs.mkdir(parent, name, new)
+        })
+    }
+
+    /* Replaces the IMMUTABLE and APPEND_ONLY flags of a file or directory on behalf of the user 'uid'. Only
+     * root (0) may change them, anyone else gets EPERM */
+    pub fn set_flags<P: AsRef<Path>>(&self, path: P, flags: u32, uid: u32) -> io::Result<FileAttr> {
+        self.change("set_flags", |fs| {
+            let ino = fs.resolve(path.as_ref())?;
+            fs.set_flags(ino, flags, uid)
+        })
+    }
+
+    /* Lists the entries of a directory */
+    pub fn readdir<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<DirEntry>> {
+        let fs = self.lock();
+        let ino = fs.resolve(path.as_ref()).map_err(io::Error::from_raw_os_error)?;
+        let mut entries = Vec::new();
+        // Cookies 1 and 2 are '.' and '..', starting after them leaves them out
+        fs.readdir(ino, 2, &mut |ino, _, kind, name| {
+            entries.push(DirEntry { name: name.to_os_string(), ino, kind });
+            false
+        }).map_err(io::Error::from_raw_os_error)?;
+        Ok(entries)
+    }
+
+    /* Returns the attributes of a file or directory */
+    pub fn stat<P: AsRef<Path>>(&self, path: P) -> io::Result<FileAttr> {
+        let fs = self.lock();
+        let ino = fs.resolve(path.as_ref()).map_err(io::Error::from_raw_os_error)?;
+        fs.getattr(ino).map_err(io::Error::from_raw_os_error)
+    }
+
+    /* Moves a file or directory to a new path */
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+        self.change("rename", |fs| {
+            let (parent, name) = fs.resolve_parent(from.as_ref())?;
+            let (newparent, newname) = fs.resolve_parent(to.as_ref())?;
+            fs.rename(parent, name, newparent, newname)
+        })
+    }
+
+    /* Gives the file at 'original' the new path 'link' as well, like std::fs::hard_link */
+    pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(&self, original: P, link: Q) -> io::Result<FileAttr> {
+        self.change("link", |fs| {
+            let ino = fs.resolve(original.as_ref())?;
+            let (newparent, newname) = fs.resolve_parent(link.as_ref())?;
+            fs.link(ino, newparent, newname)
+        })
+    }
+
+    /* Removes anything but a directory, like unlink(2) */
+    pub fn remove_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.change("remove_file", |fs| {
+            let (parent, name) = fs.resolve_parent(path.as_ref())?;
+            fs.unlink(parent, name)
+        })
+    }
+
+    /* Removes an empty directory, like rmdir(2) */
+    pub fn remove_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.change("remove_dir", |fs| {
+            let (parent, name) = fs.resolve_parent(path.as_ref())?;
+            fs.rmdir(parent, name)
+        })
+    }
+
+    /* Removes a file, or a directory as long as it is empty */
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.change("remove", |fs| {
+            let (parent, name) = fs.resolve_parent(path.as_ref())?;
+            if fs.lookup(parent, name)?.kind == FileType::Directory { fs.rmdir(parent, name) } else { fs.unlink(parent, name) }
+        })
+    }
+}
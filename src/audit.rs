@@ -0,0 +1,174 @@
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use libc::c_int;
+use fuse::Request;
+use super::{Inner, RamFS};
+use super::metrics::errno_name;
+
+// How many rotated logs are kept next to the current one, as 'audit.log.1' (newest) to 'audit.log.5'
+const KEEP: u32 = 5;
+
+/* An append-only file of JSON lines, one per mutating operation, rotated once it reaches 'max_size' bytes */
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+/* Who asked for an operation and what it was aimed at */
+pub(crate) struct AuditEntry<'a> {
+    pub req: &'a Request<'a>,
+    pub op: &'static str,
+    pub parent: u64,
+    pub name: Option<&'a OsStr>, // None when 'parent' is the inode the operation is on
+    pub to: Option<(u64, &'a OsStr)>, // where a rename moves things to, or the entry a link adds
+}
+
+impl<'a> AuditEntry<'a> {
+    pub fn new(req: &'a Request<'a>, op: &'static str, parent: u64, name: Option<&'a OsStr>) -> AuditEntry<'a> {
+        AuditEntry { req, op, parent, name, to: None }
+    }
+}
+
+/* Appends 'value' to 'out' as a quoted JSON string */
+fn json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl AuditLog {
+    /* Opens, or starts, the log at 'path'. The path is made absolute so rotation keeps working after the
+     * daemon changes its directory */
+    pub fn open(path: &Path, max_size: u64) -> io::Result<AuditLog> {
+        let path = std::path::absolute(path)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditLog { path, file, size, max_size })
+    }
+
+    /* Moves the current log to '.1', shifting older ones up and dropping the oldest, and starts a new one */
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: u32| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        for n in (1..KEEP).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        fs::rename(&self.path, rotated(1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl RamFS {
+    /* Starts recording every change made through the mount to 'path', replacing any log already in use */
+    pub fn set_audit_log(&self, path: &Path, max_size: u64) -> io::Result<()> {
+        self.lock().audit = Some(AuditLog::open(path, max_size)?);
+        Ok(())
+    }
+}
+
+impl Inner {
+    /* Writes one line for an operation that has finished, failing with 'error' if it is set. The paths must
+     * have been taken before the operation ran, as removing or renaming changes them */
+    pub(crate) fn audit(&mut self, entry: &AuditEntry, path: &str, to: Option<&str>, error: Option<c_int>) {
+        let audit = match self.audit.as_mut() {
+            Some(audit) => audit,
+            None => return,
+        };
+        let mut line = String::new();
+        let _ = write!(line, "{{\"time\":\"{}\",\"uid\":{},\"gid\":{},\"pid\":{},\"op\":\"{}\",\"path\":",
+            time::now_utc().rfc3339(), entry.req.uid(), entry.req.gid(), entry.req.pid(), entry.op);
+        json_string(&mut line, path);
+        if let Some(to) = to {
+            line.push_str(",\"to\":");
+            json_string(&mut line, to);
+        }
+        match error {
+            None => line.push_str(",\"result\":\"ok\",\"errno\":0}\n"),
+            Some(err) => {
+                let _ = writeln!(line, ",\"result\":\"{}\",\"errno\":{}}}", errno_name(err), err);
+            }
+        }
+        if let Err(err) = audit.append(&line) {
+            error!("audit log {}: {}", audit.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quoted(value: &str) -> String {
+        let mut out = String::new();
+        json_string(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(quoted("plain"), "\"plain\"");
+        assert_eq!(quoted("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quoted("a\\b"), "\"a\\\\b\"");
+        assert_eq!(quoted("one\ntwo"), "\"one\\ntwo\"");
+        assert_eq!(quoted("tab\there\u{1}\u{1f}"), "\"tab\\u0009here\\u0001\\u001f\"");
+        // Only control characters are escaped, the rest of Unicode goes through as it is
+        assert_eq!(quoted("\u{7f}é"), "\"\u{7f}é\"");
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("ramfs-audit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("audit.log");
+        let rotated = |n: u32| fs::read_to_string(dir.join(format!("audit.log.{}", n))).ok();
+        let mut log = AuditLog::open(&path, 10).unwrap();
+        log.append("first\n").unwrap();
+        log.append("second\n").unwrap();
+        assert_eq!(rotated(1).as_deref(), Some("first\n"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        log.append("third\n").unwrap();
+        assert_eq!(rotated(1).as_deref(), Some("second\n"));
+        assert_eq!(rotated(2).as_deref(), Some("first\n"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        // A line longer than the limit still goes into a file of its own rather than being split
+        log.append("a rather long line\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a rather long line\n");
+        // Only KEEP old logs stay around
+        for n in 0..KEEP {
+            log.append(&format!("line {}\n", n)).unwrap();
+        }
+        assert_eq!(rotated(KEEP).as_deref(), Some("a rather long line\n"));
+        assert_eq!(rotated(KEEP + 1), None);
+        // Reopening carries on with the size already on disk
+        let mut log = AuditLog::open(&path, 10).unwrap();
+        log.append("again\n").unwrap();
+        assert_eq!(rotated(1).as_deref(), Some(format!("line {}\n", KEEP - 1).as_str()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
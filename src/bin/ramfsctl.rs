@@ -0,0 +1,60 @@
+// Command-line client for the control socket a ramfs serves with --control
+use std::env;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::process;
+use ramfs::control::quote;
+
+fn usage(program: &str) -> String {
+    format!("Usage: {} [-s SOCKET] <command> [args...]
+
+SOCKET defaults to $RAMFS_CONTROL. Run '{} -s SOCKET help' to list the commands.
+", program, program)
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let program = args.remove(0);
+
+    let mut socket = env::var("RAMFS_CONTROL").ok();
+    if args.first().map(String::as_str) == Some("-s") {
+        if args.len() < 2 {
+            eprint!("{}", usage(&program));
+            process::exit(2);
+        }
+        socket = Some(args.remove(1));
+        args.remove(0);
+    }
+    let socket = match socket {
+        Some(socket) if !args.is_empty() && args[0] != "-h" && args[0] != "--help" => socket,
+        _ => {
+            eprint!("{}", usage(&program));
+            process::exit(2);
+        }
+    };
+
+    // Send the command as one line, then everything the server writes back is the reply. Arguments are
+    // quoted as needed, so a path with spaces in it stays one word
+    let line: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+    let reply = UnixStream::connect(&socket).and_then(|mut stream| {
+        writeln!(stream, "{}", line.join(" "))?;
+        stream.shutdown(Shutdown::Write)?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        Ok(reply)
+    });
+    match reply {
+        Ok(reply) => match reply.strip_prefix("ok\n") {
+            Some(body) => print!("{}", body),
+            None => {
+                eprint!("{}: {}", program, reply);
+                process::exit(1);
+            }
+        },
+        Err(err) => {
+            eprintln!("{}: {}: {}", program, socket, err);
+            process::exit(1);
+        }
+    }
+}
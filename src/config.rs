@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/* Tunables a RamFS is created with. Everything not set explicitly keeps the value ramfs always had:
+ * a writable file-system whose root directory is owned by root with mode 0755, no limits at all, and
+ * names and attributes cached by the kernel for a second */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub root_mode: u16,
+    pub root_uid: u32,
+    pub root_gid: u32,
+    pub max_size: Option<u64>, // bytes of file data, writes past it fail with ENOSPC
+    pub max_inodes: Option<u64>, // files and directories including the root, creating more fails with ENOSPC
+    pub max_file_size: Option<u64>, // bytes a single file may hold, growing one past it fails with EFBIG
+    pub read_only: bool, // every change fails with EROFS, RamFS::set_read_only flips it at runtime
+    pub read_only_on_panic: bool, // switch to read-only once an operation panics, so a bug cannot do more damage
+    pub entry_ttl: Duration, // how long the kernel may trust a name it looked up
+    pub attr_ttl: Duration, // how long the kernel may trust the attributes it was given
+    pub negative_ttl: Option<Duration>, // how long the kernel may remember a name is missing, None to always ask again
+    pub keep_cache: bool, // keep a file's page cache across opens instead of dropping it on every open
+    pub direct_io: bool, // send every read and write of a file here, bypassing the page cache
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            root_mode: 0o755, // The initial permission for a directory according to ramFS in Linux
+            root_uid: 0,
+            root_gid: 0,
+            max_size: None,
+            max_inodes: None,
+            max_file_size: None,
+            read_only: false,
+            read_only_on_panic: false,
+            entry_ttl: Duration::from_secs(1),
+            attr_ttl: Duration::from_secs(1),
+            negative_ttl: None,
+            keep_cache: false,
+            direct_io: false,
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use super::{logging, options, RamFS, APPEND_ONLY, IMMUTABLE};
+
+// The protocol is one command line per connection, its words separated by spaces. A word holding spaces
+// or quotes is sent in double quotes, with '\"', '\\' and '\n' inside them, as 'quote' writes it. The reply
+// starts with a line saying 'ok' followed by whatever the command prints, or is a single 'error: <reason>'
+// line. ramfsctl speaks it for you.
+
+const HELP: &str = "commands:
+    stats                               usage and limits
+    metrics                             per-operation counters in Prometheus text format
+    set-limit [size=N|none] [nr_inodes=N|none] [max_file_size=N|none]
+    snapshot list|create NAME|restore NAME|remove NAME
+    dump                                every file and directory in the live tree
+    fsck                                check the live tree and snapshots for inconsistencies
+    set-ro on|off                       switch read-only mode
+    drop-caches                         give spare buffer capacity back to the allocator
+    chattr [+-=][ia] PATH               set or clear the immutable and append-only flags of a file
+    lsattr PATH                         show the flags of a file
+    log-level FILTER                    e.g. 'debug' or 'ramfs=trace'
+";
+
+/* A control socket being served on a thread of its own. The socket file goes away when this is dropped */
+pub struct ControlServer {
+    path: PathBuf,
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/* Starts serving control commands for 'fs' on a Unix socket at 'path', only reachable by its owner */
+pub fn serve(fs: RamFS, path: &Path) -> io::Result<ControlServer> {
+    // A socket left behind by a ramfs that did not exit cleanly is replaced, one still in use is not
+    // Made absolute, so the socket is still found to remove after the daemon changed its directory
+    let path = std::path::absolute(path)?;
+    if UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, "another ramfs is serving this control socket"));
+    }
+    // Only a socket is ever removed, a mistyped path must not cost anyone a file
+    match fs::symlink_metadata(&path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(&path)?,
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the control socket path exists and is not a socket")),
+        Err(_) => {}
+    }
+    // The socket has to be private from the moment it exists, whoever connects can run any command as us
+    let umask = unsafe { libc::umask(0o177) };
+    let bound = UnixListener::bind(&path);
+    unsafe { libc::umask(umask) };
+    let listener = bound?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| handle(&fs, stream));
+            if let Err(err) = result {
+                warn!("control: {}", err);
+            }
+        }
+    });
+    Ok(ControlServer { path })
+}
+
+/* Reads one command from the connection and writes back its reply */
+fn handle(fs: &RamFS, stream: UnixStream) -> io::Result<()> {
+    // Connections are served one at a time, so a client that never sends anything must not hold up the rest
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    info!("control: {}", line.trim());
+
+    let uid = peer_uid(&stream)?;
+    let mut out = &stream;
+    let reply = split(&line).and_then(|words| execute(fs, &words.iter().map(String::as_str).collect::<Vec<_>>(), uid));
+    match reply {
+        Ok(body) => write!(out, "ok\n{}", body),
+        Err(msg) => writeln!(out, "error: {}", msg),
+    }
+}
+
+/* Writes 'word' the way the command line carries it, in double quotes if it would not survive as it is */
+pub fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return word.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in word.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/* Splits a command line into its words, undoing what 'quote' did */
+fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.trim_end_matches('\n').chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut word = String::new();
+        match chars.peek() {
+            None => return Ok(words),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some(c) => word.push(c),
+                            None => return Err("unterminated escape".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated quote".to_string()),
+                    }
+                }
+                if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                    return Err("expected a space after a quoted word".to_string());
+                }
+            }
+            Some(_) => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+/* Returns the uid of the process at the other end of 'stream', as the kernel saw it connect */
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ptr = &mut cred as *mut libc::ucred as *mut libc::c_void;
+    if unsafe { libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, ptr, &mut len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/* Runs one command for the user 'uid' and returns what it printed */
+fn execute(fs: &RamFS, words: &[&str], uid: u32) -> Result<String, String> {
+    match words {
+        ["help"] => Ok(HELP.to_string()),
+        ["stats"] => Ok(fs.stats().to_string()),
+        ["metrics"] => Ok(fs.metrics()),
+        ["set-limit", limits @ ..] if !limits.is_empty() => {
+            let stats = fs.stats();
+            let (mut max_size, mut max_inodes) = (stats.max_size, stats.max_inodes);
+            let mut max_file_size = None;
+            for limit in limits {
+                let (key, value) = limit.split_once('=').ok_or_else(|| format!("expected key=value, got '{}'", limit))?;
+                let value = match value {
+                    "none" => None,
+                    value => Some(options::parse_scaled(value, key != "nr_inodes")?),
+                };
+                match key {
+                    "size" => max_size = value,
+                    "nr_inodes" => max_inodes = value,
+                    "max_file_size" => max_file_size = Some(value),
+                    _ => return Err(format!("unknown limit '{}'", key)),
+                }
+            }
+            fs.set_limits(max_size, max_inodes);
+            if let Some(max_file_size) = max_file_size {
+                fs.set_max_file_size(max_file_size);
+            }
+            Ok(String::new())
+        }
+        ["snapshot", "list"] => Ok(fs.snapshot_names().iter().map(|name| format!("{}\n", name)).collect()),
+        ["snapshot", "create", name] => fs.snapshot(name).map(|_| String::new()).map_err(|err| err.to_string()),
+        ["snapshot", "restore", name] => fs.restore_snapshot(name).map(|_| String::new()).map_err(|err| err.to_string()),
+        ["snapshot", "remove", name] => fs.remove_snapshot(name).map(|_| String::new()).map_err(|err| err.to_string()),
+        ["dump"] => Ok(fs.lock().dump()),
+        ["fsck"] => {
+            let problems = fs.fsck();
+            match problems.len() {
+                0 => Ok("clean\n".to_string()),
+                count => Err(format!("{} problems found\n{}", count, problems.join("\n"))),
+            }
+        }
+        ["set-ro", "on"] => {
+            fs.set_read_only(true);
+            Ok(String::new())
+        }
+        ["set-ro", "off"] => {
+            fs.set_read_only(false);
+            Ok(String::new())
+        }
+        ["drop-caches"] => Ok(format!("freed {}\n", fs.drop_caches())),
+        ["chattr", mode, path] => {
+            let flags = chattr(mode, fs.stat(path).map_err(|err| err.to_string())?.flags)?;
+            fs.set_flags(path, flags, uid).map(|_| String::new()).map_err(|err| err.to_string())
+        }
+        ["lsattr", path] => fs.stat(path).map(|attr| format!("{} {}\n", lsattr(attr.flags), path)).map_err(|err| err.to_string()),
+        ["log-level", filter] => logging::set_filter(filter).map(|_| String::new()),
+        [] => Err("empty command, try 'help'".to_string()),
+        _ => Err(format!("invalid command '{}', try 'help'", words.join(" "))),
+    }
+}
+
+/* Applies a chattr(1) mode such as '+i', '-a' or '=ia' to the current 'flags' */
+fn chattr(mode: &str, flags: u32) -> Result<u32, String> {
+    let mut chars = mode.chars();
+    let op = chars.next();
+    let mut changed = 0;
+    for c in chars {
+        changed |= match c {
+            'i' => IMMUTABLE,
+            'a' => APPEND_ONLY,
+            _ => return Err(format!("unsupported flag '{}', only 'i' and 'a' are", c)),
+        };
+    }
+    match op {
+        Some('+') => Ok(flags | changed),
+        Some('-') => Ok(flags & !changed),
+        Some('=') => Ok(changed),
+        _ => Err(format!("invalid mode '{}', expected +, - or = followed by flags", mode)),
+    }
+}
+
+/* Shows 'flags' the way lsattr(1) does, one column per flag */
+fn lsattr(flags: u32) -> String {
+    [(IMMUTABLE, 'i'), (APPEND_ONLY, 'a')].iter().map(|(flag, c)| if flags & flag != 0 { *c } else { '-' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(fs: &RamFS, line: &str) -> Result<String, String> {
+        let words = split(line)?;
+        execute(fs, &words.iter().map(String::as_str).collect::<Vec<_>>(), 0)
+    }
+
+    #[test]
+    fn set_limit() {
+        let fs = RamFS::new();
+        run(&fs, "set-limit size=2m nr_inodes=2k max_file_size=1g").unwrap();
+        let stats = fs.stats();
+        assert_eq!((stats.max_size, stats.max_inodes), (Some(2 << 20), Some(2 << 10)));
+        assert_eq!(fs.lock().config.max_file_size, Some(1 << 30));
+        // A limit left out keeps its value, 'none' lifts it
+        run(&fs, "set-limit size=none").unwrap();
+        let stats = fs.stats();
+        assert_eq!((stats.max_size, stats.max_inodes), (None, Some(2 << 10)));
+        assert_eq!(fs.lock().config.max_file_size, Some(1 << 30));
+        run(&fs, "set-limit max_file_size=none").unwrap();
+        assert_eq!(fs.lock().config.max_file_size, None);
+        // Nothing changes when any of them is wrong
+        assert!(run(&fs, "set-limit size=1m colour=1").unwrap_err().contains("unknown limit 'colour'"));
+        assert!(run(&fs, "set-limit size").unwrap_err().contains("expected key=value"));
+        assert!(run(&fs, "set-limit size=lots").is_err());
+        assert!(run(&fs, "set-limit nr_inodes=1t").is_err()); // only sizes go up to terabytes
+        assert!(run(&fs, "set-limit").is_err());
+        assert_eq!(fs.stats().max_size, None);
+    }
+
+    #[test]
+    fn chattr_modes() {
+        assert_eq!(chattr("+i", 0), Ok(IMMUTABLE));
+        assert_eq!(chattr("+a", IMMUTABLE), Ok(IMMUTABLE | APPEND_ONLY));
+        assert_eq!(chattr("-a", IMMUTABLE | APPEND_ONLY), Ok(IMMUTABLE));
+        assert_eq!(chattr("=ia", 0), Ok(IMMUTABLE | APPEND_ONLY));
+        assert_eq!(chattr("=", IMMUTABLE), Ok(0));
+        assert!(chattr("i", 0).is_err());
+        assert!(chattr("+x", 0).unwrap_err().contains("'x'"));
+        assert!(chattr("", 0).is_err());
+        assert_eq!(lsattr(IMMUTABLE), "i-");
+        assert_eq!(lsattr(APPEND_ONLY), "-a");
+    }
+
+    #[test]
+    fn chattr_command() {
+        let fs = RamFS::new();
+        fs.write("/my file", b"data").unwrap();
+        let path = quote("/my file");
+        run(&fs, &format!("chattr +i {}", path)).unwrap();
+        assert_eq!(run(&fs, &format!("lsattr {}", path)).unwrap(), "i- /my file\n");
+        run(&fs, &format!("chattr =a {}", path)).unwrap();
+        assert_eq!(fs.stat("/my file").unwrap().flags, APPEND_ONLY);
+        assert!(run(&fs, "chattr +i /missing").is_err());
+        assert!(run(&fs, "chattr +i").unwrap_err().contains("invalid command"));
+        // Only root changes the flags
+        let err = execute(&fs, &["chattr", "-a", "/my file"], 1000).unwrap_err();
+        assert!(err.contains("not permitted"), "{}", err);
+        assert_eq!(fs.stat("/my file").unwrap().flags, APPEND_ONLY);
+    }
+
+    #[test]
+    fn quoting() {
+        for word in ["plain", "", "with space", "tab\there", "\"quoted\"", "back\\slash", "new\nline"] {
+            assert_eq!(split(&format!("lsattr {}\n", quote(word))).unwrap(), ["lsattr", word]);
+        }
+        assert_eq!(quote("/plain/path"), "/plain/path");
+        assert_eq!(split("  a   b\t c \n").unwrap(), ["a", "b", "c"]);
+        assert!(split("lsattr \"/open").is_err());
+        assert!(split("lsattr \"/a\"b").is_err());
+        assert!(run(&RamFS::new(), "").unwrap_err().contains("empty command"));
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use fuse::FileType;
+
+// Cookies 1 and 2 belong to '.' and '..', which are never stored
+const DOT_COOKIES: u64 = 2;
+// What each entry adds to the size of a directory, the same made-up figure tmpfs uses
+const ENTRY_SIZE: u64 = 20;
+
+/* Returns the size reported for a directory holding 'entries' entries besides '.' and '..' */
+pub(crate) fn size(entries: usize) -> u64 {
+    (DOT_COOKIES + entries as u64) * ENTRY_SIZE
+}
+
+/* The entries of a directory. Each entry is given a cookie from a counter of the directory's own when it is
+ * added, and readdir offsets are those cookies. A listing therefore picks up right after the last entry it
+ * handed out, whatever was created or removed in between, and finds its place without walking the entries
+ * before it */
+#[derive(Debug, Clone)]
+pub(crate) struct Dir {
+    by_name: BTreeMap<OsString, (u64, u64)>, // inode and cookie
+    by_cookie: BTreeMap<u64, OsString>,
+    last_cookie: u64,
+}
+
+impl Dir {
+    pub fn new() -> Dir {
+        Dir { by_name: BTreeMap::new(), by_cookie: BTreeMap::new(), last_cookie: DOT_COOKIES }
+    }
+
+    pub fn get(&self, name: &OsStr) -> Option<&u64> {
+        self.by_name.get(name).map(|(ino, _)| ino)
+    }
+
+    pub fn contains_key(&self, name: &OsStr) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    /* Links 'name' to 'ino'. A name that was already there is replaced, and listed again as a new entry */
+    pub fn insert(&mut self, name: OsString, ino: u64) {
+        self.remove(&name);
+        self.last_cookie += 1;
+        self.by_cookie.insert(self.last_cookie, name.clone());
+        self.by_name.insert(name, (ino, self.last_cookie));
+    }
+
+    pub fn remove(&mut self, name: &OsStr) -> Option<u64> {
+        let (ino, cookie) = self.by_name.remove(name)?;
+        self.by_cookie.remove(&cookie);
+        Some(ino)
+    }
+
+    /* Iterates over the entries in name order */
+    pub fn iter(&self) -> impl Iterator<Item = (&OsString, &u64)> {
+        self.by_name.iter().map(|(name, (ino, _))| (name, ino))
+    }
+
+    /* Iterates over the entries that come after the cookie 'offset', in the order they are listed in */
+    pub fn after(&self, offset: i64) -> impl Iterator<Item = (i64, &OsStr, u64)> {
+        let start = (offset.max(0) as u64).max(DOT_COOKIES) + 1;
+        self.by_cookie.range(start..).map(move |(cookie, name)| (*cookie as i64, name.as_os_str(), self.by_name[name].0))
+    }
+}
+
+/* Hands '.' and '..' and then 'entries' to 'add', leaving out everything up to and including the cookie
+ * 'offset', until 'add' returns true because the reply is full */
+pub(crate) fn list<'a>(ino: u64, parent: u64, offset: i64, entries: impl Iterator<Item = (u64, i64, FileType, &'a OsStr)>,
+        add: &mut dyn FnMut(u64, i64, FileType, &OsStr) -> bool) {
+    let dots = [(ino, 1, FileType::Directory, OsStr::new(".")), (parent, 2, FileType::Directory, OsStr::new(".."))];
+    for (ino, cookie, kind, name) in dots.into_iter().chain(entries) {
+        if cookie > offset && add(ino, cookie, kind, name) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(names: &[&str]) -> Dir {
+        let mut dir = Dir::new();
+        for (ino, name) in names.iter().enumerate() {
+            dir.insert(OsString::from(name), ino as u64 + 10);
+        }
+        dir
+    }
+
+    // One readdir reply holding at most 'max' entries, with the cookie to ask for the next one from
+    fn page(dir: &Dir, offset: i64, max: usize) -> (Vec<String>, i64) {
+        let mut names = Vec::new();
+        let mut next = offset;
+        let entries = dir.after(offset).map(|(cookie, name, ino)| (ino, cookie, FileType::RegularFile, name));
+        list(1, 1, offset, entries, &mut |_, cookie, _, name| {
+            if names.len() == max {
+                return true; // Full, this entry is left for the next reply
+            }
+            names.push(name.to_string_lossy().into_owned());
+            next = cookie;
+            false
+        });
+        (names, next)
+    }
+
+    #[test]
+    fn after_skips_dots_and_listed_entries() {
+        let dir = dir(&["b", "a", "c"]);
+        let names = |offset| dir.after(offset).map(|(_, name, _)| name.to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names(0), ["b", "a", "c"]);
+        assert_eq!(names(-1), ["b", "a", "c"]);
+        assert_eq!(names(2), ["b", "a", "c"]);
+        assert_eq!(names(4), ["c"]);
+        assert_eq!(names(5), Vec::<&str>::new());
+        assert_eq!(page(&dir, 0, 3).0, [".", "..", "b"]);
+    }
+
+    #[test]
+    fn paging_across_changes() {
+        let mut dir = dir(&["a", "b", "c", "d", "e", "f", "g", "h"]);
+        let mut seen = Vec::new();
+        let mut offset = 0;
+        // Between every two replies one entry not listed yet is unlinked and a new one created
+        for (gone, new) in [("d", "i"), ("a", "j"), ("j", "k"), ("x", "l")] {
+            let (names, next) = page(&dir, offset, 3);
+            seen.extend(names);
+            offset = next;
+            dir.remove(OsStr::new(gone));
+            dir.insert(OsString::from(new), 99);
+        }
+        loop {
+            let (names, next) = page(&dir, offset, 3);
+            if names.is_empty() {
+                break;
+            }
+            seen.extend(names);
+            offset = next;
+        }
+        // 'a' went after it was listed, 'd' and 'j' before, and every other entry shows up exactly once
+        assert_eq!(seen, [".", "..", "a", "b", "c", "e", "f", "g", "h", "i", "k", "l"]);
+    }
+
+    #[test]
+    fn replaced_name_is_listed_again() {
+        let mut dir = dir(&["a", "b"]);
+        let (names, offset) = page(&dir, 0, 3);
+        assert_eq!(names, [".", "..", "a"]);
+        dir.insert(OsString::from("a"), 42);
+        let (names, _) = page(&dir, offset, 3);
+        assert_eq!(names, ["b", "a"]);
+        assert_eq!(dir.get(OsStr::new("a")), Some(&42));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use fuse::FileType;
+use super::{dir, Inner, Payload, RamFS, Tree};
+
+impl Tree {
+    /* Checks that the inode table is consistent and returns a description of every problem found.
+     * An empty list means the tree is consistent */
+    pub(crate) fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let name = |ino: u64| match self.paths(ino).into_iter().next() {
+            Some(path) => path.display().to_string(),
+            None => format!("<inode {}>", ino),
+        };
+
+        match self.get(1) {
+            Some(root) if root.entries().is_some() => {}
+            Some(_) => problems.push("the root is not a directory".to_string()),
+            None => problems.push("the root is missing".to_string()),
+        }
+
+        // Every inode is stored under its own number, its kind matches what it holds and file sizes add up
+        let mut total_size = 0;
+        for (ino, inode) in self.inodes.iter() {
+            let attr = &inode.attr;
+            if attr.ino != *ino {
+                problems.push(format!("{} is stored under inode {} but its attributes say {}", name(*ino), ino, attr.ino));
+            }
+            match &inode.payload {
+                Payload::Directory(entries) => {
+                    // '.', the entry in the parent and the '..' of every subdirectory
+                    let subdirs = entries.iter().filter(|(_, child)| self.get(**child).is_some_and(|child| child.is_dir())).count();
+                    if attr.nlink != 2 + subdirs as u32 {
+                        problems.push(format!("{} has nlink {} but {} subdirectories", name(*ino), attr.nlink, subdirs));
+                    }
+                    if attr.size != dir::size(entries.len()) {
+                        problems.push(format!("{} has size {} but {} entries", name(*ino), attr.size, entries.len()));
+                    }
+                }
+                Payload::File(file) => {
+                    if attr.size != file.get_file_size() {
+                        problems.push(format!("{} has size {} but holds {} bytes", name(*ino), attr.size, file.get_file_size()));
+                    }
+                    total_size += file.get_file_size() as i64;
+                }
+                Payload::Symlink(_) | Payload::Device(_) => {}
+            }
+            if attr.kind != inode.payload.kind() {
+                problems.push(format!("{} is a {:?} but does not hold one", name(*ino), attr.kind));
+            }
+        }
+        if total_size != self.fs_size {
+            problems.push(format!("fs_size is {} but the files hold {} bytes", self.fs_size, total_size));
+        }
+        if let Some(last) = self.inodes.keys().max() {
+            if *last > self.next_inode {
+                problems.push(format!("inode {} is above next_inode {}", last, self.next_inode));
+            }
+        }
+
+        // Every directory entry points to a live inode
+        let mut links: BTreeMap<u64, u32> = BTreeMap::new();
+        for (dir, entries) in self.inodes.iter().filter_map(|(dir, inode)| inode.entries().map(|entries| (dir, entries))) {
+            for (entry, child) in entries.iter() {
+                if !self.inodes.contains_key(child) {
+                    problems.push(format!("{}/{} points to missing inode {}", name(*dir), entry.to_string_lossy(), child));
+                }
+                *links.entry(*child).or_insert(0) += 1;
+            }
+        }
+
+        // Every inode but the root is linked from somewhere: a directory exactly once, anything else 'nlink'
+        // times, and the parent an inode records really has it under the name it records
+        for (ino, inode) in self.inodes.iter().filter(|(ino, _)| **ino != 1) {
+            let count = links.get(ino).copied().unwrap_or(0);
+            if count == 0 {
+                problems.push(format!("inode {} is not linked from any directory", ino));
+                continue;
+            }
+            match inode.payload.kind() {
+                FileType::Directory if count != 1 => problems.push(format!("{} is linked {} times", name(*ino), count)),
+                FileType::Directory => {}
+                _ if count != inode.attr.nlink => {
+                    problems.push(format!("{} has nlink {} but {} links", name(*ino), inode.attr.nlink, count))
+                }
+                _ => {}
+            }
+            let linked = self.get(inode.root).and_then(|parent| parent.entries()).and_then(|entries| entries.get(&inode.name));
+            if linked != Some(ino) {
+                problems.push(format!("inode {} says it is '{}' in inode {}, which does not link to it", ino, inode.name.to_string_lossy(), inode.root));
+            }
+        }
+        if links.contains_key(&1) {
+            problems.push("the root is linked from a directory".to_string());
+        }
+        problems
+    }
+}
+
+impl RamFS {
+    /* Checks the live tree and every snapshot for inconsistencies in their inode tables
+     * and returns a description of each one found. An empty list means all is well */
+    pub fn fsck(&self) -> Vec<String> {
+        let fs = self.lock();
+        let mut problems = fs.tree.check();
+        for (snapshot, tree) in fs.snapshots.trees() {
+            problems.extend(tree.check().into_iter().map(|problem| format!("snapshot {}: {}", snapshot, problem)));
+        }
+        problems
+    }
+}
+
+impl Inner {
+    /* Run after every operation in debug builds, so whatever breaks the tree is caught in the act */
+    pub(crate) fn debug_check(&self, op: &str) {
+        let problems = self.tree.check();
+        for problem in &problems {
+            error!("fsck after {}: {}", op, problem);
+        }
+        assert!(problems.is_empty(), "{} left the tree inconsistent", op);
+    }
+}
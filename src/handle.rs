@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+
+/* The file handles open and create hand out, with the flags each one was opened with. The kernel only passes
+ * the flags to open and create, later calls just name the handle */
+#[derive(Default)]
+pub(crate) struct Handles {
+    flags: HashMap<u64, u32>,
+    rendered: HashMap<u64, Vec<u8>>, // what an introspection file held when the handle was opened
+    last: u64,
+}
+
+impl Handles {
+    /* Hands out a new handle for a file opened with 'flags' */
+    pub fn open(&mut self, flags: u32) -> u64 {
+        self.last += 1;
+        self.flags.insert(self.last, flags);
+        self.last
+    }
+
+    /* Returns the flags a handle was opened with, nothing is set for one that is not open */
+    pub fn flags(&self, fh: u64) -> u32 {
+        self.flags.get(&fh).copied().unwrap_or(0)
+    }
+
+    /* Keeps the contents an introspection file had when 'fh' was opened, so its reads all see the same text */
+    pub fn keep(&mut self, fh: u64, contents: Vec<u8>) {
+        self.rendered.insert(fh, contents);
+    }
+
+    pub fn rendered(&self, fh: u64) -> Option<&[u8]> {
+        self.rendered.get(&fh).map(Vec::as_slice)
+    }
+
+    pub fn release(&mut self, fh: u64) {
+        self.flags.remove(&fh);
+        self.rendered.remove(&fh);
+    }
+}
@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use libc::{c_int, ENOENT, EISDIR, ENOTDIR};
+use fuse::{FileAttr, FileType};
+use super::{dir, Inner};
+use super::snapshot::SNAPSHOT_DIR_INO;
+
+// Name of the hidden, read-only directory at the root of the mount whose files describe the file-system
+// itself, much like /proc. Its contents are generated from the live state every time a file is opened
+pub const INTROSPECT_DIR: &str = ".ramfs";
+
+// The directory and its files sit just below the '.snapshots' directory, at the very top of the range
+// the live tree hands out inode numbers from, so they are never reached in practice
+pub const INTROSPECT_DIR_INO: u64 = SNAPSHOT_DIR_INO - 1;
+const FILES: [&str; 3] = ["stats", "inodes", "config"];
+
+/* Tells if an inode is the introspection directory or one of its files */
+pub fn owns(ino: u64) -> bool {
+    ino <= INTROSPECT_DIR_INO && ino > INTROSPECT_DIR_INO - 1 - FILES.len() as u64
+}
+
+/* Tells if 'name' under 'parent' is the introspection directory itself */
+pub fn is_dir_entry(parent: u64, name: &OsStr) -> bool {
+    parent == 1 && name == INTROSPECT_DIR
+}
+
+fn file_ino(index: usize) -> u64 {
+    INTROSPECT_DIR_INO - 1 - index as u64
+}
+
+impl Inner {
+    /* Returns the path of the introspection directory or of one of its files */
+    pub(crate) fn introspect_path(&self, ino: u64) -> PathBuf {
+        let dir = Path::new("/").join(INTROSPECT_DIR);
+        match FILES.get((INTROSPECT_DIR_INO - 1).wrapping_sub(ino) as usize) {
+            Some(file) => dir.join(file),
+            None => dir,
+        }
+    }
+
+    /* Renders one of the introspection files */
+    fn introspect_render(&self, ino: u64) -> Result<String, c_int> {
+        match FILES.get((INTROSPECT_DIR_INO - 1).wrapping_sub(ino) as usize) {
+            Some(&"stats") => Ok(self.stats().to_string()),
+            Some(&"inodes") => Ok(self.dump()),
+            Some(&"config") => {
+                let limit = |limit: Option<u64>| limit.map_or("none".to_string(), |value| value.to_string());
+                let mut out = String::new();
+                let _ = write!(out, "root_mode {:04o}\nroot_uid {}\nroot_gid {}\nmax_size {}\nmax_inodes {}\nmax_file_size {}\nread_only {}\nerrors {}\n",
+                    self.config.root_mode, self.config.root_uid, self.config.root_gid, limit(self.config.max_size),
+                    limit(self.config.max_inodes), limit(self.config.max_file_size), if self.config.read_only { "on" } else { "off" },
+                    if self.config.read_only_on_panic { "remount-ro" } else { "continue" });
+                let _ = write!(out, "entry_timeout {}\nattr_timeout {}\nnegative_timeout {}\nkeep_cache {}\ndirect_io {}\n",
+                    self.config.entry_ttl.as_secs_f64(), self.config.attr_ttl.as_secs_f64(),
+                    self.config.negative_ttl.unwrap_or_default().as_secs_f64(),
+                    if self.config.keep_cache { "on" } else { "off" }, if self.config.direct_io { "on" } else { "off" });
+                Ok(out)
+            }
+            _ => Err(ENOENT),
+        }
+    }
+
+    /* Attributes of the introspection directory and its files, borrowed from the live root with the write
+     * bits dropped. Like in /proc the files have a size of 0: rendering 'inodes' on every stat would walk the
+     * whole tree, and as they are opened with direct I/O the kernel reads them to the end regardless */
+    pub(crate) fn introspect_getattr(&self, ino: u64) -> Result<FileAttr, c_int> {
+        let mut attr = self.tree.inodes[&1].attr;
+        attr.ino = ino;
+        attr.perm = 0o555;
+        attr.nlink = 2;
+        attr.size = dir::size(FILES.len());
+        if ino != INTROSPECT_DIR_INO {
+            attr.kind = FileType::RegularFile;
+            attr.perm = 0o444;
+            attr.nlink = 1;
+            attr.size = 0;
+        }
+        Ok(attr)
+    }
+
+    /* Looks up a file under '.ramfs', or '.ramfs' itself under the live root */
+    pub(crate) fn introspect_lookup(&self, parent: u64, name: &OsStr) -> Result<FileAttr, c_int> {
+        if parent == 1 {
+            return self.introspect_getattr(INTROSPECT_DIR_INO);
+        }
+        if parent != INTROSPECT_DIR_INO {
+            self.introspect_getattr(parent)?;
+            return Err(ENOTDIR); // The files have no entries
+        }
+        let index = FILES.iter().position(|file| name == *file).ok_or(ENOENT)?;
+        self.introspect_getattr(file_ino(index))
+    }
+
+    pub(crate) fn introspect_readdir(&self, ino: u64, offset: i64, add: &mut dyn FnMut(u64, i64, FileType, &OsStr) -> bool) -> Result<(), c_int> {
+        if ino != INTROSPECT_DIR_INO {
+            self.introspect_getattr(ino)?;
+            return Err(ENOTDIR);
+        }
+        let files = FILES.iter().enumerate().map(|(index, file)| (file_ino(index), index as i64 + 3, FileType::RegularFile, OsStr::new(file)));
+        dir::list(ino, 1, offset, files, add);
+        Ok(())
+    }
+
+    /* Renders an introspection file for the handle 'fh' it is being opened with, anything else is left alone */
+    pub(crate) fn introspect_open(&mut self, ino: u64, fh: u64) -> Result<(), c_int> {
+        if owns(ino) && ino != INTROSPECT_DIR_INO {
+            let contents = self.introspect_render(ino)?.into_bytes();
+            self.handles.keep(fh, contents);
+        }
+        Ok(())
+    }
+
+    /* Hands back the contents of an introspection file as they were when 'fh' was opened, or freshly
+     * rendered ones when reading without a handle */
+    pub(crate) fn introspect_read(&self, ino: u64, fh: u64) -> Result<Cow<'_, [u8]>, c_int> {
+        if ino == INTROSPECT_DIR_INO {
+            return Err(EISDIR);
+        }
+        match self.handles.rendered(fh) {
+            Some(contents) => Ok(Cow::Borrowed(contents)),
+            None => Ok(Cow::Owned(self.introspect_render(ino)?.into_bytes())),
+        }
+    }
+
+    /* Lists the live tree one line per inode: kind, mode, owner, size, inode number and path */
+    pub(crate) fn dump(&self) -> String {
+        let mut out = String::new();
+        let mut pending = vec![(1, "/".to_string())];
+        while let Some((ino, path)) = pending.pop() {
+            if let Ok(attr) = self.getattr(ino) {
+                let kind = if attr.kind == FileType::Directory { 'd' } else { '-' };
+                let _ = writeln!(out, "{} {:04o} {}:{} {} {} {}", kind, attr.perm, attr.uid, attr.gid, attr.size, ino, path);
+            }
+            // Children are pushed in reverse so they come off the stack, and get printed, in name order
+            let mut children = Vec::new();
+            if self.readdir(ino, 2, &mut |child, _, _, name| { children.push((name.to_os_string(), child)); false }).is_ok() {
+                children.sort();
+                for (name, child) in children.into_iter().rev() {
+                    let child_path = if path == "/" { format!("/{}", name.to_string_lossy()) } else { format!("{}/{}", path, name.to_string_lossy()) };
+                    pending.push((child, child_path));
+                }
+            }
+        }
+        out
+    }
+}
@@ -5,26 +5,67 @@ extern crate time;
 extern crate log;
 extern crate env_logger;
 
-use std::iter;
-use std::collections::BTreeMap;
-use std::ffi::OsStr;
-use libc::{ENOENT, EINVAL, EEXIST, ENOTEMPTY};
-use fuse::{FileAttr, FileType, Filesystem, Request,
+mod api;
+mod audit;
+mod config;
+mod dir;
+pub mod control;
+mod fsck;
+mod handle;
+mod introspect;
+mod lock;
+pub mod logging;
+mod metrics;
+pub mod options;
+mod ramfile;
+mod snapshot;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use libc::{c_int, ENOENT, EINVAL, EEXIST, EIO, EISDIR, ENAMETOOLONG, ENOTDIR, ENOTEMPTY, ENOSPC, EFBIG, EOPNOTSUPP, EPERM, EROFS, O_ACCMODE, O_APPEND, O_EXCL, O_RDONLY, O_TRUNC};
+use fuse::consts::{FOPEN_DIRECT_IO, FOPEN_KEEP_CACHE};
+use fuse::{Filesystem, Request,
     ReplyAttr, ReplyData, ReplyEntry, ReplyDirectory,
     ReplyEmpty, ReplyWrite, ReplyOpen, ReplyCreate};
 use time::Timespec; // This library is used to get system-time
+use snapshot::{Snapshots, SNAPSHOT_DIR, SNAPSHOT_DIR_INO};
+use metrics::Metrics;
+use audit::{AuditEntry, AuditLog};
+use dir::Dir;
+use handle::Handles;
+use lock::{Lock, Locks, Owner};
+
+pub use fuse::{FileAttr, FileType};
+pub use api::DirEntry;
+pub use config::Config;
+pub use metrics::serve_http as serve_metrics;
+pub use ramfile::{RamFile, ReadDir};
+
+// Longest name a directory entry can have, in bytes
+const NAME_MAX: usize = 255;
+
+// The chattr(1) flags ramfs honours, kept in FileAttr.flags with the values Linux gives FS_IMMUTABLE_FL and
+// FS_APPEND_FL. An immutable inode cannot be changed, renamed or removed, and neither can the entries of an
+// immutable directory. An append-only file can only grow at its end, an append-only directory only gain entries
+pub const IMMUTABLE: u32 = 0x10;
+pub const APPEND_ONLY: u32 = 0x20;
 
 #[derive(Debug, Clone, Default)]
 pub struct File {
     data: Vec<u8>, // had to change this because append_data is a vector of 8-bit unsigned int
-    is_updated: bool,
-    old_size: i64,
 }
 
 impl File {
     /* Creates a new attribute for File*/
     fn new_file() -> File {
-        File{data: Vec::new(), is_updated: false, old_size: 0}
+        File{data: Vec::new()}
     }
 
     /* Returns the number of bytes of data in the file*/
@@ -32,478 +73,1418 @@ impl File {
         self.data.len() as u64 // Returning a unsigned 64 integer because that's what the FileAttr needs for size
     }
 
-    /* Appends the file with new data at a specific offset*/
-    fn update_file(&mut self, offset: i64, append_data: &[u8]) -> u64 {
+    /* Writes new data into the file at a specific offset, overwriting whatever was there before*/
+    fn update_file(&mut self, offset: i64, append_data: &[u8]) -> Result<u64, c_int> {
         let offset: usize = offset as usize;
+        let end = offset.checked_add(append_data.len()).ok_or(EFBIG)?;
 
-        if offset >= self.data.len() {
-            self.data.extend(iter::repeat(0).take(offset - self.data.len())); // Extending with 0s until we reach the offset bit
-            self.data.splice(offset.., append_data.iter().cloned()); // Basically we are appending the new data after the offset as there is no data after that
+        if end > self.data.len() {
+            self.reserve(end)?;
+            self.data.resize(end, 0); // Extending with 0s so the new data fits, this also fills any hole before the offset
         }
-        else if offset + append_data.len() > self.data.len() {
-            self.data.splice(offset.., append_data.iter().cloned()); // Same as before
+        self.data[offset..end].copy_from_slice(append_data);
+        Ok(append_data.len() as u64)
+    }
+
+    /* Shortens the vector, keeping the first 'size' elements and dropping the rest, or pads it with 0s up to 'size'. */
+    fn truncate_bytes(&mut self, size: u64) -> Result<(), c_int> {
+        let size = usize::try_from(size).map_err(|_| EFBIG)?;
+        if size > self.data.len() {
+            self.reserve(size)?;
         }
-        else {
-            self.data.splice(offset..offset, append_data.iter().cloned()); // Here we want to append data right after the offset
+        self.data.resize(size, 0);
+        Ok(())
+    }
+
+    /* Makes room for the data to grow to 'size' bytes. Running out of memory fails with ENOSPC, like a full
+     * disk, instead of aborting the whole process */
+    fn reserve(&mut self, size: usize) -> Result<(), c_int> {
+        let additional = size.saturating_sub(self.data.len());
+        // Growing by doubling asks for up to twice as much, which may fail where the exact amount would not
+        self.data.try_reserve(additional).or_else(|_| self.data.try_reserve_exact(additional)).map_err(|_| ENOSPC)
+    }
+
+    /* Returns the data of a file to change, copying it first if a snapshot still shares it. The copy can run
+     * out of memory as well, which fails with ENOSPC */
+    fn make_mut(file: &mut Arc<File>) -> Result<&mut File, c_int> {
+        if Arc::get_mut(file).is_none() {
+            let mut data = Vec::new();
+            data.try_reserve_exact(file.data.len()).map_err(|_| ENOSPC)?;
+            data.extend_from_slice(&file.data);
+            *file = Arc::new(File { data });
         }
-        append_data.len() as u64
+        Ok(Arc::make_mut(file)) // no longer shared, so nothing is copied here
     }
+}
 
-    /* Shortens the vector, keeping the first 'size' elements and dropping the rest. */
-    fn truncate_bytes(&mut self, size: u64) {
-        self.data.truncate(size as usize);
+/* What an inode holds besides its attributes. This is also what makes it a directory, a file and so on:
+ * the kind in its attributes is taken from here, so an operation can never find file data on a directory
+ * or entries in a file */
+#[derive(Debug, Clone)]
+enum Payload {
+    Directory(Dir), // all the successive directories/files, by name
+    File(Arc<File>), // behind an Arc of its own, so changing the attributes of a file never copies its data
+    // Nothing makes these two yet, the file-system has no mknod or symlink operation
+    #[allow(dead_code)]
+    Symlink(PathBuf),
+    #[allow(dead_code)]
+    Device(FileType), // fifos, sockets and device nodes, all there is to them is in the attributes
+}
+
+impl Payload {
+    /* The kind of inode holding this */
+    fn kind(&self) -> FileType {
+        match self {
+            Payload::Directory(_) => FileType::Directory,
+            Payload::File(_) => FileType::RegularFile,
+            Payload::Symlink(_) => FileType::Symlink,
+            Payload::Device(kind) => *kind,
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Inode {
-    name: String,
-    nodes: BTreeMap<String, u64>, // Binary-tree for all the successive directories/files
+    attr: FileAttr,
+    name: OsString, // any bytes but '/' and NUL, not necessarily UTF-8
     root: u64, // Represents the Inode number for parent directory
-
-    // Most of the Inode attributes mentioned in 'fs.h' would be handled by fuse::FileAttr
+    payload: Payload,
 }
 
 impl Inode {
-    fn new_inode(label: String, parent: u64) -> Inode {
-        Inode{name: label, nodes: BTreeMap::new(), root: parent,}
+    /* Returns the entries of a directory, or None if this is not one */
+    fn entries(&self) -> Option<&Dir> {
+        match &self.payload {
+            Payload::Directory(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /* Returns the data of a regular file, or None if this is not one */
+    fn file(&self) -> Option<&File> {
+        match &self.payload {
+            Payload::File(file) => Some(file),
+            _ => None,
+        }
+    }
+
+    /* Returns the data of a regular file, or the errno for reading or writing whatever this is instead */
+    fn file_or_err(&self) -> Result<&File, c_int> {
+        match &self.payload {
+            Payload::File(file) => Ok(file),
+            Payload::Directory(_) => Err(EISDIR),
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.payload, Payload::Directory(_))
     }
 }
 
-pub struct RamFS {
-    files: BTreeMap<u64, File>,
-    attrs: BTreeMap<u64, FileAttr>,
-    inodes: BTreeMap<u64, Inode>,
+/* The live file-system and every snapshot are a Tree: a single table from inode number to inode. The table
+ * sits behind an Arc and so does every inode inside it, so cloning a Tree only copies a pointer. The first
+ * mutation after a clone copies the table (pointers only) and then the single inode being changed. */
+#[derive(Clone)]
+struct Tree {
+    inodes: Arc<HashMap<u64, Arc<Inode>>>,
     next_inode: u64,
     fs_size: i64,
 }
 
+impl Tree {
+    fn get(&self, ino: u64) -> Option<&Inode> {
+        self.inodes.get(&ino).map(|inode| &**inode)
+    }
+
+    /* Returns a mutable inode, un-sharing it from any snapshot first */
+    fn inode_mut(inodes: &mut Arc<HashMap<u64, Arc<Inode>>>, ino: u64) -> Option<&mut Inode> {
+        Arc::make_mut(inodes).get_mut(&ino).map(Arc::make_mut)
+    }
+
+    fn insert(&mut self, inode: Inode) {
+        Arc::make_mut(&mut self.inodes).insert(inode.attr.ino, Arc::new(inode));
+    }
+
+    /* Adds the entry 'name' for the existing inode 'ino' to the directory 'dir', where the name must be free.
+     * The size of a directory follows its number of entries, and a subdirectory adds one to its link count
+     * through its '..' */
+    fn link(&mut self, dir: u64, name: OsString, ino: u64) {
+        let is_dir = self.get(ino).is_some_and(Inode::is_dir);
+        if let Some(inode) = Tree::inode_mut(&mut self.inodes, dir) {
+            if let Payload::Directory(entries) = &mut inode.payload {
+                entries.insert(name, ino);
+                inode.attr.size = dir::size(entries.len());
+                if is_dir {
+                    inode.attr.nlink += 1;
+                }
+            }
+        }
+    }
+
+    /* Removes the entry 'name' from the directory 'dir' and returns the inode it linked to, which is left in
+     * the table */
+    fn unlink(&mut self, dir: u64, name: &OsStr) -> Option<u64> {
+        let ino = *self.get(dir)?.entries()?.get(name)?;
+        let is_dir = self.get(ino).is_some_and(Inode::is_dir);
+        let inode = Tree::inode_mut(&mut self.inodes, dir)?;
+        if let Payload::Directory(entries) = &mut inode.payload {
+            entries.remove(name);
+            inode.attr.size = dir::size(entries.len());
+            if is_dir {
+                inode.attr.nlink -= 1;
+            }
+        }
+        Some(ino)
+    }
+
+    /* Drops an inode that no directory links to any more, along with its data */
+    fn remove(&mut self, ino: u64) {
+        if let Some(inode) = Arc::make_mut(&mut self.inodes).remove(&ino) {
+            if let Some(file) = inode.file() {
+                self.fs_size -= file.get_file_size() as i64;
+            }
+        }
+    }
+
+    /* Removes the entry 'name' of 'dir' to a file and drops the file along with its last link. A file that
+     * still has others and was known by this one takes the name and parent of another instead */
+    fn unlink_file(&mut self, dir: u64, name: &OsStr) {
+        let Some(ino) = self.unlink(dir, name) else { return };
+        let Some(inode) = Tree::inode_mut(&mut self.inodes, ino) else { return };
+        inode.attr.nlink -= 1;
+        if inode.attr.nlink == 0 {
+            self.remove(ino);
+            return;
+        }
+        if inode.root != dir || inode.name != name {
+            return;
+        }
+        let other = self.inodes.iter()
+            .filter_map(|(dir, inode)| inode.entries().map(|entries| (*dir, entries)))
+            .find_map(|(dir, entries)| entries.iter().find(|(_, child)| **child == ino).map(|(name, _)| (dir, name.clone())));
+        if let (Some((dir, name)), Some(inode)) = (other, Tree::inode_mut(&mut self.inodes, ino)) {
+            inode.root = dir;
+            inode.name = name;
+        }
+    }
+}
+
+/* The attribute changes a setattr call asks for, anything left as None stays as it is */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct SetAttr {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    size: Option<u64>,
+    atime: Option<Timespec>,
+    mtime: Option<Timespec>,
+    crtime: Option<Timespec>,
+}
+
+/* The permission bits and owner an inode is created with */
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct NewAttr {
+    perm: u16,
+    uid: u32,
+    gid: u32,
+}
+
+impl NewAttr {
+    /* What a FUSE request creating an inode with 'mode' asks for. The kernel has already taken the caller's
+     * umask off 'mode', since the fuse crate never asks to be handed it (FUSE_DONT_MASK) */
+    fn from_request(req: &Request, mode: u32) -> NewAttr {
+        NewAttr { perm: (mode & 0o7777) as u16, uid: req.uid(), gid: req.gid() }
+    }
+}
+
+/* A point-in-time view of the usage and limits of a file-system */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub inodes: u64,
+    pub bytes: u64, // bytes of file data in the live tree
+    pub snapshots: u64,
+    pub max_size: Option<u64>,
+    pub max_inodes: Option<u64>,
+    pub read_only: bool,
+}
+
+impl fmt::Display for Stats {
+    /* One 'key value' line per field, limits that are not set show as 'none' */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limit = |limit: Option<u64>| limit.map_or("none".to_string(), |value| value.to_string());
+        write!(f, "inodes {}\nbytes {}\nsnapshots {}\nmax_size {}\nmax_inodes {}\nread_only {}\n",
+            self.inodes, self.bytes, self.snapshots, limit(self.max_size), limit(self.max_inodes),
+            if self.read_only { "on" } else { "off" })
+    }
+}
+
+/* A handle to an in-memory file-system. Clones share the same tree, so one clone can be mounted
+ * through FUSE while another is used through the path API in 'api.rs' */
+#[derive(Clone)]
+pub struct RamFS {
+    inner: Arc<Mutex<Inner>>,
+    metrics: Arc<Metrics>, // kept outside the lock so counting an operation never waits for the tree
+}
+
+/* Everything the file-system keeps in memory. The FUSE handlers and the path API both go through
+ * the inode-level operations below, they only differ in how they hand back the errno */
+struct Inner {
+    tree: Tree,
+    snapshots: Snapshots,
+    config: Config,
+    audit: Option<AuditLog>,
+    handles: Handles,
+    locks: Locks,
+    shut_down: bool,
+}
+
+impl Default for RamFS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RamFS {
     pub fn new() -> RamFS {
-        let files_stub = BTreeMap::new(); 
-        let root = Inode::new_inode("/".to_string(), 1 as u64); // Setting '/' as root and assigning Inode value for it to 1
-        
+        RamFS::with_config(Config::default())
+    }
+
+    /* Creates a file-system whose root directory and limits come from 'config' */
+    pub fn with_config(config: Config) -> RamFS {
         let ts = time::now().to_timespec();
-        let mut root_dir_attrs = BTreeMap::new();
+        let payload = Payload::Directory(Dir::new());
         let attr = FileAttr { // Defining attributes for root directory
             ino: 1, //u64 Since the inode-value of our root directory is 1
-            size: 0, //u64,
+            size: dir::size(0), //u64,
             blocks: 1, //u64,
             atime: ts, //Timespec,
             mtime: ts, //Timespec,
             ctime: ts, //Timespec,
             crtime: ts, //Timespec,
-            kind: FileType::Directory, //FileType,
-            perm: 0o755, //u16, The initial permission for a directory according to ramFS in Linux
-            nlink: 0, //u32,
-            uid: 0, //u32,
-            gid: 0, //u32,
+            kind: payload.kind(), //FileType,
+            perm: config.root_mode, //u16,
+            nlink: 2, //u32, '.' and the root's own '..'
+            uid: config.root_uid, //u32,
+            gid: config.root_gid, //u32,
             rdev: 0, //u32,
             flags: 0, //u32,
         };
-        root_dir_attrs.insert(1, attr); // Updating root-directory attributes
+        // Setting '/' as root and assigning Inode value for it to 1
+        let root = Inode { attr, name: OsString::from("/"), root: 1, payload };
 
-        let mut root_dir_inode = BTreeMap::new();
-        root_dir_inode.insert(1, root); // Adding the root directory inode in the FS
+        let mut inodes = HashMap::new();
+        inodes.insert(1, Arc::new(root)); // Adding the root directory inode in the FS
 
-        RamFS {
-            files: files_stub,
-            attrs: root_dir_attrs,
-            inodes: root_dir_inode,
+        let tree = Tree {
+            inodes: Arc::new(inodes),
             next_inode: 2, // Moving in order after creating the initial root directory
             fs_size: 0,
+        };
+        RamFS {
+            inner: Arc::new(Mutex::new(Inner { tree, snapshots: Snapshots::default(), config, audit: None, handles: Handles::default(), locks: Locks::default(), shut_down: false })),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    /* Switches the file-system between read-only and read-write while it keeps running.
+     * Any clone of the handle can do this, including one kept back when mounting */
+    pub fn set_read_only(&self, read_only: bool) {
+        self.lock().config.read_only = read_only;
+    }
+
+    /* Tells if writes are currently turned away with EROFS */
+    pub fn is_read_only(&self) -> bool {
+        self.lock().config.read_only
+    }
+
+    /* Returns how much the file-system currently holds and what it is allowed to hold */
+    pub fn stats(&self) -> Stats {
+        self.lock().stats()
+    }
+
+    /* Changes the size and inode limits of a running file-system, None lifts a limit. Lowering a limit
+     * below what is already in use only stops further growth, nothing gets removed */
+    pub fn set_limits(&self, max_size: Option<u64>, max_inodes: Option<u64>) {
+        let mut fs = self.lock();
+        fs.config.max_size = max_size;
+        fs.config.max_inodes = max_inodes;
+    }
+
+    /* Changes the largest file a running file-system allows, None lifts the limit. Files already past a new
+     * limit keep their data, they only cannot grow any further */
+    pub fn set_max_file_size(&self, max_file_size: Option<u64>) {
+        self.lock().config.max_file_size = max_file_size;
+    }
+
+    /* Gives the spare capacity file buffers have grown into back to the allocator and returns how many
+     * bytes that freed. Files still shared with a snapshot are left alone, copying them would cost more */
+    pub fn drop_caches(&self) -> u64 {
+        let mut fs = self.lock();
+        let mut freed = 0;
+        for inode in Arc::make_mut(&mut fs.tree.inodes).values_mut() {
+            if let Some(Payload::File(file)) = Arc::get_mut(inode).map(|inode| &mut inode.payload) {
+                if let Some(file) = Arc::get_mut(file) {
+                    freed += (file.data.capacity() - file.data.len()) as u64;
+                    file.data.shrink_to_fit();
+                }
+            }
+        }
+        freed
+    }
+
+    /* Runs the final report once the file-system is going away. The kernel only sends 'destroy' for some
+     * kinds of mounts, so the binary calls this too after unmounting, only the first call does anything */
+    pub fn shutdown(&self) {
+        let mut fs = self.lock();
+        if fs.shut_down {
+            return;
+        }
+        fs.shut_down = true;
+        info!("shutting down with {} inodes, {} bytes of data and {} snapshots",
+            fs.tree.inodes.len(), fs.tree.fs_size, fs.snapshots.len());
+    }
+
+    /* Locks the shared state for the duration of one operation. A panic that got past 'isolated' while the
+     * lock was held has been reported already, so the poisoning is ignored rather than failing every later call */
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /* Runs 'f' on the locked state, turning a panic into EIO so a bug hit by one request cannot take the
+     * whole mount down with it. 'ino' is the inode the request was about, for the log. Debug builds check
+     * the tree after every operation, and a check that fails is answered the same way */
+    fn isolated<T>(&self, fs: &mut Inner, op: &'static str, ino: u64, f: impl FnOnce(&mut Inner) -> Result<T, c_int>) -> Result<T, c_int> {
+        let checked = |fs: &mut Inner| {
+            let result = f(fs);
+            if cfg!(debug_assertions) {
+                fs.debug_check(op);
+            }
+            result
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| checked(fs))) {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = payload.downcast_ref::<&str>().copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown cause");
+                error!("{} on inode {} panicked: {}", op, ino, message);
+                // The operation may have been half way through a change, 'fsck' tells whether it left a mess behind
+                for problem in fs.tree.check() {
+                    error!("fsck after {}: {}", op, problem);
+                }
+                self.metrics.add_panic(op);
+                if fs.config.read_only_on_panic && !fs.config.read_only {
+                    warn!("switching to read-only after {} panicked", op);
+                    fs.config.read_only = true;
+                }
+                Err(EIO)
+            }
+        }
+    }
+
+    /* Runs one FUSE callback about the inode 'ino' against the shared state and counts it in the metrics under 'name' */
+    fn op<T>(&self, name: &'static str, ino: u64, f: impl FnOnce(&mut Inner) -> Result<T, c_int>) -> Result<T, c_int> {
+        let start = Instant::now();
+        let mut fs = self.lock();
+        let result = self.isolated(&mut fs, name, ino, f);
+        drop(fs);
+        self.metrics.record(name, start.elapsed(), result.as_ref().err().copied());
+        result
+    }
+
+    /* Same as 'op' for a callback that changes something, which is also written to the audit log if one is set */
+    fn audited<T>(&self, entry: AuditEntry, f: impl FnOnce(&mut Inner) -> Result<T, c_int>) -> Result<T, c_int> {
+        let start = Instant::now();
+        let mut fs = self.lock();
+        let paths = fs.audit.is_some().then(|| {
+            let path = entry.name.map_or_else(|| fs.display_path(entry.parent), |name| fs.entry_path(entry.parent, name));
+            (path, entry.to.map(|(parent, name)| fs.entry_path(parent, name)))
+        });
+        let result = self.isolated(&mut fs, entry.op, entry.parent, f);
+        let error = result.as_ref().err().copied();
+        if let Some((path, to)) = paths {
+            fs.audit(&entry, &path, to.as_deref(), error);
+        }
+        drop(fs);
+        self.metrics.record(entry.op, start.elapsed(), error);
+        result
+    }
+}
+
+impl Inner {
+    fn stats(&self) -> Stats {
+        Stats {
+            inodes: self.tree.inodes.len() as u64,
+            bytes: self.tree.fs_size as u64,
+            snapshots: self.snapshots.len() as u64,
+            max_size: self.config.max_size,
+            max_inodes: self.config.max_inodes,
+            read_only: self.config.read_only,
         }
     }
 
     /* Returns the next inode value in filesystem tree*/
     fn get_next_inode(&mut self) -> u64 { // This is function is straight-up from ramFS in linux
-        self.next_inode += 1;
-        self.next_inode
+        self.tree.next_inode += 1;
+        self.tree.next_inode
     }
-}
 
-// Out of all the function that the fuse::FileSystem implements there are handful of them which need tweaking
-impl Filesystem for RamFS {
+    /* Fails with EROFS while the file-system is switched to read-only */
+    fn check_writable(&self) -> Result<(), c_int> {
+        if self.config.read_only {
+            return Err(EROFS);
+        }
+        Ok(())
+    }
 
-    /* This function gets the file's attributes for specified 'ino' value */
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        match self.attrs.get_mut(&ino) {
-            Some(attr) => {
-                reply.attr(&Timespec::new(1,0), attr);
+    /* Fails with EROFS for an inode of a snapshot or of '.ramfs', neither of which can ever be modified */
+    fn check_not_hidden(&self, ino: u64) -> Result<(), c_int> {
+        if snapshot::owns(ino) || introspect::owns(ino) {
+            return Err(EROFS);
+        }
+        Ok(())
+    }
+
+    /* Same as 'check_not_hidden' for the entry 'name' of 'parent', which also turns away '.snapshots' and
+     * '.ramfs' themselves */
+    fn check_not_hidden_entry(&self, parent: u64, name: &OsStr) -> Result<(), c_int> {
+        self.check_not_hidden(parent)?;
+        if (parent == 1 && name == SNAPSHOT_DIR) || introspect::is_dir_entry(parent, name) {
+            return Err(EROFS);
+        }
+        Ok(())
+    }
+
+    /* Fails with EPERM when 'ino' carries any of the inode 'flags' */
+    fn check_flags(&self, ino: u64, flags: u32) -> Result<(), c_int> {
+        match self.tree.get(ino) {
+            Some(inode) if inode.attr.flags & flags != 0 => Err(EPERM),
+            _ => Ok(()),
+        }
+    }
+
+    /* Replaces the IMMUTABLE and APPEND_ONLY flags of 'ino', which only root ('uid' 0) is allowed to do.
+     * Any other flag is not supported */
+    fn set_flags(&mut self, ino: u64, flags: u32, uid: u32) -> Result<FileAttr, c_int> {
+        self.check_writable()?;
+        self.check_not_hidden(ino)?;
+        if flags & !(IMMUTABLE | APPEND_ONLY) != 0 {
+            return Err(EOPNOTSUPP);
+        }
+        if self.tree.get(ino).ok_or(ENOENT)?.attr.flags != flags && uid != 0 {
+            return Err(EPERM);
+        }
+        let inode = Tree::inode_mut(&mut self.tree.inodes, ino).ok_or(ENOENT)?;
+        inode.attr.flags = flags;
+        Ok(inode.attr)
+    }
+
+    /* Opening is where writers get turned away early, both from snapshots and from a read-only file-system.
+     * O_TRUNC empties the file. Returns a new file handle and the FOPEN_* flags to reply with */
+    fn open(&mut self, ino: u64, flags: u32) -> Result<(u64, u32), c_int> {
+        let wants_write = flags as c_int & O_ACCMODE != O_RDONLY || flags as c_int & O_TRUNC != 0;
+        if wants_write {
+            self.check_writable()?;
+            self.check_not_hidden(ino)?;
+        }
+        if self.getattr(ino)?.kind == FileType::Directory && wants_write {
+            return Err(EISDIR); // Directories are only ever opened for reading
+        }
+        if wants_write {
+            self.check_flags(ino, IMMUTABLE)?;
+            if flags as c_int & O_APPEND == 0 || flags as c_int & O_TRUNC != 0 {
+                self.check_flags(ino, APPEND_ONLY)?; // Nothing but appending is allowed
             }
+        }
+        if flags as c_int & O_TRUNC != 0 {
+            self.setattr(ino, SetAttr { size: Some(0), ..SetAttr::default() })?;
+        }
+        let fh = self.handles.open(flags);
+        self.introspect_open(ino, fh)?;
+        Ok((fh, self.open_flags(ino)))
+    }
+
+    /* The FOPEN_* flags an open of 'ino' is answered with, as the mount options ask */
+    fn open_flags(&self, ino: u64) -> u32 {
+        // The introspection files change under the page cache, so their reads always come through here
+        if self.config.direct_io || introspect::owns(ino) {
+            return FOPEN_DIRECT_IO;
+        }
+        if self.config.keep_cache { FOPEN_KEEP_CACHE } else { 0 }
+    }
+
+    /* How long the kernel may keep the attributes of a getattr or setattr reply */
+    fn attr_ttl(&self) -> Timespec {
+        timespec(self.config.attr_ttl)
+    }
+
+    /* How long the kernel may keep an entry reply. The fuse crate gives its name and attributes the same
+     * timeout, so the shorter one is used and attributes are never kept for longer than asked */
+    fn entry_ttl(&self) -> Timespec {
+        timespec(self.config.entry_ttl.min(self.config.attr_ttl))
+    }
+
+    /* How long the kernel may remember that a name does not exist, None to not remember it at all */
+    fn negative_ttl(&self) -> Option<Timespec> {
+        self.config.negative_ttl.map(timespec)
+    }
+
+    /* Closes the handle 'fh' of 'ino', with the locks taken through it */
+    fn release(&mut self, ino: u64, fh: u64) {
+        self.handles.release(fh);
+        self.locks.release(ino, fh);
+    }
+
+    /* Takes or drops a lock on 'ino' without waiting, EAGAIN if another owner is in the way. Locks are
+     * taken on any inode, snapshots and read-only file-systems included, since they change no data */
+    fn setlk(&mut self, ino: u64, owner: Owner, lock: Lock) -> Result<(), c_int> {
+        self.getattr(ino)?;
+        lock::check(&lock)?;
+        self.locks.set(ino, owner, lock)
+    }
+
+    /* Fails with ENOSPC when the file-system already holds as many inodes as it is allowed to */
+    fn check_inode_limit(&self) -> Result<(), c_int> {
+        match self.config.max_inodes {
+            Some(max) if self.tree.inodes.len() as u64 >= max => Err(ENOSPC),
+            _ => Ok(()),
+        }
+    }
+
+    /* Fails with ENOSPC when growing a file from 'old_size' to 'new_size' bytes would go over the size limit */
+    fn check_size_limit(&self, old_size: u64, new_size: u64) -> Result<(), c_int> {
+        let grow = new_size.saturating_sub(old_size);
+        match self.config.max_size {
+            Some(max) if grow > 0 && self.tree.fs_size as u64 + grow > max => Err(ENOSPC),
+            _ => Ok(()),
+        }
+    }
+
+    /* Fails with EFBIG when a file would grow past the largest one allowed */
+    fn check_file_size(&self, size: u64) -> Result<(), c_int> {
+        match self.config.max_file_size {
+            Some(max) if size > max => Err(EFBIG),
+            _ => Ok(()),
+        }
+    }
+
+    /* This function gets the file's attributes for specified 'ino' value */
+    fn getattr(&self, ino: u64) -> Result<FileAttr, c_int> {
+        if snapshot::owns(ino) {
+            return self.snapshot_getattr(ino);
+        }
+        if introspect::owns(ino) {
+            return self.introspect_getattr(ino);
+        }
+        match self.tree.get(ino) {
+            Some(inode) => Ok(inode.attr),
             // If no matching inode value found, then throw error
             None => {
                 error!("getattr: Cannot find inode: {}", ino);
-                reply.error(ENOENT); // File not found error
+                Err(ENOENT) // File not found error
             },
-        };
+        }
     }
 
     /* This function updates the FileType at 'ino' attributes */
     // There are only a handful of attributes that can actually be changed once a FileType is instantiated
-    fn setattr(&mut self, _req: &Request, ino: u64, _mode: Option<u32>, uid: Option<u32>, gid: Option<u32>, size: Option<u64>, atime: Option<Timespec>, mtime: Option<Timespec>, _fh: Option<u64>, crtime: Option<Timespec>, _chgtime: Option<Timespec>, _bkuptime: Option<Timespec>, _flags: Option<u32>, reply: ReplyAttr) {
-        match self.attrs.get_mut(&ino) {
+    fn setattr(&mut self, ino: u64, changes: SetAttr) -> Result<FileAttr, c_int> {
+        self.check_writable()?;
+        self.check_not_hidden(ino)?;
+        if changes != SetAttr::default() {
+            self.check_flags(ino, IMMUTABLE | APPEND_ONLY)?;
+        }
+        if let (Some(new_size), Some(inode)) = (changes.size, self.tree.get(ino)) {
+            inode.file_or_err()?; // Only regular files can be truncated
+            self.check_file_size(new_size)?;
+            self.check_size_limit(inode.attr.size, new_size)?;
+        }
+        let tree = &mut self.tree;
+        match Tree::inode_mut(&mut tree.inodes, ino) {
             // After getting the matched ino FileType, update the new attribute values
-            Some(attr) => {
-                match atime {
-                    Some(new_atime) => attr.atime = new_atime,
-                    None => {}
+            Some(inode) => {
+                let attr = &mut inode.attr;
+                if let (Some(new_size), Payload::File(memfile)) = (changes.size, &mut inode.payload) {
+                    // First actually update the bytes in the file and then update the attr value. This is the
+                    // one change that can fail, so it goes before the others
+                    File::make_mut(memfile)?.truncate_bytes(new_size)?;
+                    tree.fs_size += new_size as i64 - attr.size as i64;
+                    attr.size = new_size;
                 }
-                match mtime {
-                    Some(new_mtime) => attr.mtime = new_mtime,
-                    None => {}
+                if let Some(new_atime) = changes.atime {
+                    attr.atime = new_atime;
                 }
-                match crtime {
-                    Some(new_crtime) => attr.crtime = new_crtime,
-                    None => {}
+                if let Some(new_mtime) = changes.mtime {
+                    attr.mtime = new_mtime;
                 }
-                match uid {
-                    Some(new_uid) => {
-                        attr.uid = new_uid;
-                    }
-                    None => {}
+                if let Some(new_crtime) = changes.crtime {
+                    attr.crtime = new_crtime;
                 }
-                match gid {
-                    Some(new_gid) => {
-                        attr.gid = new_gid;
-                    }
-                    None => {}
+                if let Some(new_uid) = changes.uid {
+                    attr.uid = new_uid;
                 }
-                match size {
-                    Some(new_size) => {
-                        if let Some(memfile) = self.files.get_mut(&ino) {
-                            // First actually update the bytes in the file and then update the attr value
-                            memfile.truncate_bytes(new_size);
-                            attr.size = new_size;
-                        }
-                    }
-                    None => {}
+                if let Some(new_gid) = changes.gid {
+                    attr.gid = new_gid;
                 }
-                reply.attr(&Timespec::new(1,0), attr);
+                Ok(*attr)
             }
             None => {
                 error!("setattr: Cannot find inode: {}", ino);
-                reply.error(ENOENT); // File not found error
+                Err(ENOENT) // File not found error
             }
         }
     }
 
-    /* This function reads all the files and directory in the current directory */
-    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
-        let mut entries = Vec::new(); // Create an empty Vec to push all the files into this
-        entries.push((ino, FileType::Directory, ".")); // pushing this directory first
-        if let Some(inode) = self.inodes.get(&ino) {
-            entries.push((inode.root, FileType::Directory, "..")); // pushing parent directory second
-            for (child, child_ino) in &inode.nodes { // pushing all the children in this directory
-                let child_attrs = &self.attrs.get(child_ino).unwrap();
-                entries.push((child_attrs.ino, child_attrs.kind, &child));
+    /* This function lists the files and directories in a directory, '.' and '..' first. It starts after the
+     * entry whose cookie is 'offset' and hands each one to 'add' until that returns true for a full reply */
+    fn readdir(&self, ino: u64, offset: i64, add: &mut dyn FnMut(u64, i64, FileType, &OsStr) -> bool) -> Result<(), c_int> {
+        if snapshot::owns(ino) {
+            return self.snapshot_readdir(ino, offset, add);
+        }
+        if introspect::owns(ino) {
+            return self.introspect_readdir(ino, offset, add);
+        }
+        match self.tree.get(ino) {
+            Some(inode) => {
+                let children = inode.entries().ok_or(ENOTDIR)?.after(offset)
+                    .filter_map(|(cookie, name, child)| self.tree.get(child).map(|child_inode| (child, cookie, child_inode.payload.kind(), name)));
+                dir::list(ino, inode.root, offset, children, add);
+                Ok(())
             }
-            if entries.len() > 0 {
-                // Basically we need to add all the entries in ReplyAttr, but need to check if offset==0
-                // otherwise the readdir() will be stuck in infinite loop.
-                // When offset!=0, it means the passed offset has already been taken care off, and should skip it
-                let to_skip = if offset == 0 { offset } else { offset + 1 } as usize;
-                for (i, entry) in entries.into_iter().enumerate().skip(to_skip) {
-                    reply.add(entry.0, i as i64, entry.1, entry.2);
-                }
+            None => {
+                error!("readdir: cannot find inode: {}", ino);
+                Err(ENOENT) // File not found error
             }
-            reply.ok();
-        } 
-        else {
-            error!("readdir: cannot find inode: {}", ino);
-            reply.error(ENOENT) // File not found error
         }
     }
 
     /* This function actually replies FileType based on the 'ino' number */
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        match self.inodes.get(&parent) {
+    fn lookup(&self, parent: u64, name: &OsStr) -> Result<FileAttr, c_int> {
+        if name.len() > NAME_MAX {
+            return Err(ENAMETOOLONG);
+        }
+        if snapshot::owns(parent) || (parent == 1 && name == SNAPSHOT_DIR) {
+            return self.snapshot_lookup(parent, name);
+        }
+        if introspect::owns(parent) || introspect::is_dir_entry(parent, name) {
+            return self.introspect_lookup(parent, name);
+        }
+        match self.tree.get(parent) {
             // First get the parent inode
             Some(parent_ino) => {
-                let inode = match parent_ino.nodes.get(name.to_str().unwrap()) {
+                let inode = match parent_ino.entries().ok_or(ENOTDIR)?.get(name) {
                     Some(inode) => inode, // Find if the inode is linked to parent or not
                     None => {
-                        error!("lookup: {} is not in parent's {} children", name.to_str().unwrap(), parent);
-                        reply.error(ENOENT);
-                        return;
+                        error!("lookup: {} does not exist", self.entry_path(parent, name));
+                        return Err(ENOENT);
                     }
                 };
-                match self.attrs.get(inode) { // get the attributes of inode to send to reply
-                    Some(attr) => {
-                        reply.entry(&Timespec::new(1,0), attr, 0);
-                    }
+                match self.tree.get(*inode) { // get the attributes of inode to send to reply
+                    Some(inode) => Ok(inode.attr),
                     None => {
-                        error!("lookup: cannot find inode: {}", inode);
-                        reply.error(ENOENT); // File not found error
+                        error!("lookup: cannot find inode {} of {}", inode, self.entry_path(parent, name));
+                        Err(ENOENT) // File not found error
                     }
-                };
+                }
             },
             // Parent inode not found
             None => {
-                error!("lookup: parent inode: {} not found", parent);
-                reply.error(ENOENT); // File not found error
+                error!("lookup: parent of {} not found", self.entry_path(parent, name));
+                Err(ENOENT) // File not found error
             }
-        };
+        }
+    }
+
+    /* Returns the inode 'name' links to in the directory 'parent', if there is one */
+    fn entry(&self, parent: u64, name: &OsStr) -> Option<u64> {
+        self.tree.get(parent).and_then(Inode::entries).and_then(|entries| entries.get(name)).copied()
     }
 
     /* This function removes a directory form the file-system */
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let mut rmdir_ino = 0;
-        if let Some(parent_ino) = self.inodes.get_mut(&parent) { // first find the parent inode value
-            match parent_ino.nodes.get(&name.to_str().unwrap().to_string()) { // then check if the FileType of 'name' exists
-                Some(dir_ino) => {
-                    rmdir_ino = *dir_ino;
-                }
+    fn rmdir(&mut self, parent: u64, name: &OsStr) -> Result<(), c_int> {
+        self.check_writable()?;
+        // Removing a directory under '.snapshots' deletes that snapshot
+        if parent == SNAPSHOT_DIR_INO {
+            return self.remove_snapshot(name.to_str().ok_or(ENOENT)?);
+        }
+        check_old_name(name)?;
+        self.check_not_hidden_entry(parent, name)?;
+        let rmdir_ino = match self.tree.get(parent) { // first find the parent inode value
+            Some(parent_ino) => match parent_ino.entries().ok_or(ENOTDIR)?.get(name) { // then check if the FileType of 'name' exists
+                Some(dir_ino) => *dir_ino,
                 // If not there, return error
                 None => {
-                    error!("rmdir: {} is not in parent's {} children", name.to_str().unwrap(), parent);
-                    reply.error(ENOENT); // File not found error
-                    return;
+                    error!("rmdir: {} does not exist", self.entry_path(parent, name));
+                    return Err(ENOENT); // File not found error
                 }
+            },
+            None => {
+                error!("rmdir: parent of {} not found", self.entry_path(parent, name));
+                return Err(ENOENT); // File not found error
             }
+        };
+        if self.tree.get(rmdir_ino).is_some_and(|inode| !inode.is_dir()) {
+            return Err(ENOTDIR); // Files go through unlink()
         }
-        // Removing the FileType after searching if its there
-        if let Some(dir) = self.inodes.get_mut(&rmdir_ino) {
-            // Fist check if the directory is empty or not
-            if dir.nodes.is_empty() {
-                self.attrs.remove(&rmdir_ino);
-            } 
-            // else return error when removing
-            else {
-                error!("rmdir: failed to remove '{}': Directory not empty", dir.name);
-                reply.error(ENOTEMPTY); // File is not empty error
-                return;
-            }
+        for ino in [parent, rmdir_ino] {
+            self.check_flags(ino, IMMUTABLE | APPEND_ONLY)?;
         }
-        // If it's a file then remove it from the parent inode tree
-        if let Some(parent_ino) = self.inodes.get_mut(&parent) {
-            parent_ino.nodes.remove(&name.to_str().unwrap().to_string());
+        // Fist check if the directory is empty or not, and return error when removing if it is not
+        if self.tree.get(rmdir_ino).and_then(Inode::entries).is_some_and(|entries| !entries.is_empty()) {
+            error!("rmdir: failed to remove '{}': Directory not empty", self.display_path(rmdir_ino));
+            return Err(ENOTEMPTY); // File is not empty error
         }
-        self.inodes.remove(&rmdir_ino);
-        reply.ok();
+        // Then remove it from the parent inode tree, and from the file-system
+        self.tree.unlink(parent, name);
+        self.tree.remove(rmdir_ino);
+        Ok(())
     }
 
-    /* This function is used to create directory in the file-system */
-    fn mkdir(&mut self, _req: &Request, parent: u64, name: &OsStr, _mode: u32, reply: ReplyEntry) {
+    /* Turns away new entries in the hidden directories and under their names */
+    fn check_new_entry(&self, parent: u64, name: &OsStr) -> Result<(), c_int> {
+        check_name(name)?;
+        self.check_not_hidden(parent)?;
+        check_hidden_name(parent, name)
+    }
+
+    /* Creates an inode of any kind and links it into the directory 'parent' as 'name' */
+    fn add_inode(&mut self, parent: u64, name: &OsStr, new: NewAttr, payload: Payload) -> Result<FileAttr, c_int> {
+        // Check if a parent exists or not, and if the name is already taken
+        match self.tree.get(parent).map(Inode::entries) {
+            Some(Some(entries)) if entries.contains_key(name) => return Err(EEXIST), // File exists error
+            Some(Some(_)) => {}
+            Some(None) => return Err(ENOTDIR),
+            None => {
+                error!("cannot add {}: parent not found", self.entry_path(parent, name));
+                return Err(ENOENT); // File not found error
+            }
+        }
+        self.check_flags(parent, IMMUTABLE)?;
+        self.check_inode_limit()?;
         let ts = time::now().to_timespec();
+        let kind = payload.kind();
         let attr = FileAttr {
             ino: self.get_next_inode(), // get the next inode to add it under the parent
-            size: 0,
+            size: match &payload { // a symlink is as big as its target, a directory holds '.' and '..'
+                Payload::Symlink(target) => target.as_os_str().len() as u64,
+                Payload::Directory(entries) => dir::size(entries.len()),
+                _ => 0,
+            },
             blocks: 0,
             atime: ts,
             mtime: ts,
             ctime: ts,
             crtime: ts,
-            kind: FileType::Directory,
-            perm: 0o755,
-            nlink: 0,
-            uid: 0,
-            gid: 0,
+            kind,
+            perm: new.perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 }, // the entry in 'parent', and a directory's own '.'
+            uid: new.uid,
+            gid: new.gid,
             rdev: 0,
             flags: 0,
         };
-        // Check if a parent exists or not
-        if let Some(parent_ino) = self.inodes.get_mut(&parent) {
-            // Check if the directory already exists or not
-            if parent_ino.nodes.contains_key(name.to_str().unwrap()) {
-                reply.error(EEXIST); // File exists error
-                return;
-            }
-            // If not then just add a new dir to current parent inode
-            parent_ino.nodes.insert(name.to_str().unwrap().to_string(), attr.ino);
-            self.attrs.insert(attr.ino, attr);
-        }
-        else {
-            error!("mkdir: cannot find parent {}", parent);
-            reply.error(EINVAL); // Invalid argument error
-            return;
-        }
-        // Create a new parent inode and then add it to existing tree
-        self.inodes.insert(attr.ino, Inode::new_inode(name.to_str().unwrap().to_string(), parent));
-        reply.entry(&Timespec::new(1,0), &attr, 0)
+        self.tree.insert(Inode { attr, name: name.to_os_string(), root: parent, payload });
+        self.tree.link(parent, name.to_os_string(), attr.ino);
+        Ok(attr)
     }
 
-    /* This function to open a file similar to 'touch' command */
-    fn open(&mut self, _req: &Request, _ino: u64, _flags: u32, reply: ReplyOpen) {
-        reply.opened(0, 0);
+    /* This function is used to create directory in the file-system */
+    fn mkdir(&mut self, parent: u64, name: &OsStr, new: NewAttr) -> Result<FileAttr, c_int> {
+        self.check_writable()?;
+        // Making a directory under '.snapshots' takes a snapshot of the live tree with that name
+        if parent == SNAPSHOT_DIR_INO {
+            self.snapshot(name.to_str().ok_or(EINVAL)?)?;
+            return self.snapshot_lookup(parent, name);
+        }
+        self.check_new_entry(parent, name)?;
+        self.add_inode(parent, name, new, Payload::Directory(Dir::new()))
     }
 
     /* This function is remove a file from a parent directory */
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let mut old_ino = 0; // variable to store previous inode value
-        // first check if its not the root directory
-        if let Some(parent_ino) = self.inodes.get_mut(&parent) {
-            match parent_ino.nodes.remove(&name.to_str().unwrap().to_string()) { // check if the child is in parent or not
-                Some(ino) => {
-                    match self.attrs.remove(&ino) { // check 
-                        Some(attr) => {
-                            self.fs_size -= attr.size as i64;
-                            if attr.kind == FileType::RegularFile{
-                                self.files.remove(&ino); // if it's a file then remove it from FS
-                            }
-                            old_ino = ino; // and update the previous inode number with current inode
-                        },
-                        None => {
-                            old_ino = ino;
-                        },
-                    }
-                }
-                None => {
-                    error!("unlink: {} is not in parent's {} children", name.to_str().unwrap(), parent);
-                    reply.error(ENOENT);
-                    return;
-                }
+    fn unlink(&mut self, parent: u64, name: &OsStr) -> Result<(), c_int> {
+        self.check_writable()?;
+        self.check_not_hidden_entry(parent, name)?;
+        check_old_name(name)?;
+        // check if the child is in parent or not
+        let entries = self.tree.get(parent).ok_or(ENOENT)?.entries().ok_or(ENOTDIR)?;
+        let ino = match entries.get(name) {
+            Some(ino) => *ino,
+            None => {
+                error!("unlink: {} does not exist", self.entry_path(parent, name));
+                return Err(ENOENT);
             }
         };
-        self.inodes.remove(&old_ino); // This will remove the root directory inode if the old_ino is not updated
-        reply.ok();
+        if self.tree.get(ino).is_some_and(Inode::is_dir) {
+            return Err(EISDIR); // Directories go through rmdir()
+        }
+        for ino in [parent, ino] {
+            self.check_flags(ino, IMMUTABLE | APPEND_ONLY)?;
+        }
+        self.tree.unlink_file(parent, name); // with its last link, the data of a file goes along with it
+        Ok(())
     }
 
-    /* This function is used to create a file/dir in the file-system */
-    fn create(&mut self, _req: &Request, parent: u64, name: &OsStr, _mode: u32, _flags: u32, reply: ReplyCreate) {
-        let new_ino = self.get_next_inode(); // first get the next inode
-        match self.inodes.get_mut(&parent) {
-            Some(parent_ino) => {
-                if let Some(ino) = parent_ino.nodes.get_mut(&name.to_str().unwrap().to_string()) { // if it exists then just update ReplyCreate, time of file and exit 
-                    reply.created(&Timespec::new(1,0), self.attrs.get(&ino).unwrap(), 0, 0 ,0); 
-                    return; // no need to throw an error
-                }
-                // just create a new file if not present then
-                else {
-                    let ts = time::now().to_timespec();
-                    let attr = FileAttr {
-                        ino: new_ino, // update it with the new inode value
-                        size: 0,
-                        blocks: 0,
-                        atime: ts,
-                        mtime: ts,
-                        ctime: ts,
-                        crtime: ts,
-                        kind: FileType::RegularFile,
-                        perm: 0o755,
-                        nlink: 0,
-                        uid: 0,
-                        gid: 0,
-                        rdev: 0,
-                        flags: 0,
-                    };
-                    self.attrs.insert(attr.ino, attr); // Update file's attributes with it's respective inode value
-                    self.files.insert(attr.ino, File::new_file()); // create a new file and add it to the FS
-                    reply.created(&Timespec::new(1,0), &attr, 0, 0, 0); // update ReplyCreate with new timestamp
-                }
-                // insert current file-node with rest of the nodes
-                parent_ino.nodes.insert(name.to_str().unwrap().to_string(), new_ino);
+    /* Adds the entry 'newname' in 'newparent' as another link to the file 'ino', like link(2) */
+    fn link(&mut self, ino: u64, newparent: u64, newname: &OsStr) -> Result<FileAttr, c_int> {
+        self.check_writable()?;
+        self.check_not_hidden(ino)?;
+        self.check_new_entry(newparent, newname)?;
+        if self.tree.get(ino).ok_or(ENOENT)?.is_dir() {
+            return Err(EPERM); // A directory only ever has the one entry
+        }
+        match self.tree.get(newparent).map(Inode::entries) {
+            Some(Some(entries)) if entries.contains_key(newname) => return Err(EEXIST),
+            Some(Some(_)) => {}
+            Some(None) => return Err(ENOTDIR),
+            None => return Err(ENOENT),
+        }
+        // A protected file gets no new names, and an immutable directory no new entries
+        self.check_flags(ino, IMMUTABLE | APPEND_ONLY)?;
+        self.check_flags(newparent, IMMUTABLE)?;
+        self.tree.link(newparent, newname.to_os_string(), ino);
+        let inode = Tree::inode_mut(&mut self.tree.inodes, ino).ok_or(ENOENT)?;
+        inode.attr.nlink += 1;
+        inode.attr.ctime = time::now().to_timespec();
+        Ok(inode.attr)
+    }
+
+    /* This function is used to create a file/dir in the file-system. 'flags' are the open(2) flags,
+     * of which O_EXCL and O_TRUNC matter when the file is already there */
+    fn create(&mut self, parent: u64, name: &OsStr, flags: u32, new: NewAttr) -> Result<FileAttr, c_int> {
+        self.check_writable()?;
+        self.check_new_entry(parent, name)?;
+        // if it exists then just return it, no need to throw an error unless the caller insists on a new file
+        if let Some(inode) = self.entry(parent, name).and_then(|ino| self.tree.get(ino)) {
+            if flags as c_int & O_EXCL != 0 {
+                return Err(EEXIST);
             }
-            None => {
-                error!("create: cannot find parent: {}", parent);
-                reply.error(EINVAL); // Invalid argument error 
-                return;
+            if inode.is_dir() {
+                return Err(EISDIR);
+            }
+            if flags as c_int & O_TRUNC != 0 {
+                return self.setattr(inode.attr.ino, SetAttr { size: Some(0), ..SetAttr::default() });
             }
+            return Ok(inode.attr);
         }
-        // insert current inode value to the list of all the other inodes
-        self.inodes.insert(new_ino, Inode::new_inode(name.to_str().unwrap().to_string(), parent));
+        self.add_inode(parent, name, new, Payload::File(Arc::new(File::new_file())))
+    }
+
+    /* Writes through the handle 'fh'. Appends go wherever the end of the file is by now, which is only known
+     * under the lock, so appenders never overwrite each other whatever offset the kernel thought the end was at */
+    fn write_handle(&mut self, ino: u64, fh: u64, offset: i64, data: &[u8]) -> Result<u32, c_int> {
+        let offset = if self.handles.flags(fh) as c_int & O_APPEND != 0 { self.getattr(ino)?.size as i64 } else { offset };
+        self.write(ino, offset, data)
     }
 
     /* This function is used to write something in a file */
-    fn write(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, data: &[u8], _flags: u32, reply: ReplyWrite) {
+    fn write(&mut self, ino: u64, offset: i64, data: &[u8]) -> Result<u32, c_int> {
+        self.check_writable()?;
+        self.check_not_hidden(ino)?;
+        // Writing nothing leaves the file as it is, however far past its end 'offset' is
+        if data.is_empty() {
+            return self.tree.get(ino).ok_or(ENOENT)?.file_or_err().map(|_| 0);
+        }
+        // A write reaching past the largest file allowed stops short of it, one starting there fails
+        let data = match self.config.max_file_size {
+            Some(max) if offset as u64 >= max => return Err(EFBIG),
+            Some(max) => &data[..max.saturating_sub(offset as u64).min(data.len() as u64) as usize],
+            None => data,
+        };
+        if let Some(inode) = self.tree.get(ino) {
+            inode.file_or_err()?; // Only regular files hold data
+            self.check_flags(ino, IMMUTABLE)?;
+            if offset as u64 != inode.attr.size {
+                self.check_flags(ino, APPEND_ONLY)?;
+            }
+            self.check_size_limit(inode.attr.size, (offset as u64).checked_add(data.len() as u64).ok_or(EFBIG)?)?;
+        }
         let ts = time::now().to_timespec(); // get the current time stamp
-        //error!("ino: {}", ino);
-        //error!("next_inode: {}", self.next_inode);
-        match self.files.get_mut(&ino) { // find the file first
-            Some(fp) => {
-                match self.attrs.get_mut(&ino) { // get the file's attributes
-                    Some(attr) => {
-                        let size = fp.update_file(offset, &data); // write the additional data to the file
-                        attr.atime = ts; // update the timestamp
-                        attr.mtime = ts;
-                        attr.size = fp.get_file_size(); // update the new size to the file's attribute
-                        if size !=4096 && !fp.is_updated {
-                            self.fs_size += fp.get_file_size() as i64;
-                            fp.is_updated = true;
-                            fp.old_size = fp.get_file_size() as i64;
-                        }
-                        else if size != 4096 {
-                            self.fs_size += (fp.get_file_size() as i64) - fp.old_size;
-                            fp.old_size = fp.get_file_size() as i64;
-                        }
-                        // let mut i:u64 = 1;
-                        // let mut fs_size = 0;
-                        // while i<=(self.next_inode as u64) {
-                        //     error!("before match");
-                        //     match self.attrs.get_mut(&i) {
-                        //         Some(ar) => {
-                        //             error!("ar_size: {}", ar.size);
-                        //             fs_size += ar.size
-                        //         }
-                        //         None => {}
-                        //     }
-                        //     i = i+1;
-                        // }
-                        // error!("FS ki size: {}", fs_size);
-                        reply.written(size as u32);
-                    }
-                    None => {
-                        error!("write: cannot find ino: {}", ino);
-                        reply.error(ENOENT); // No such file or directory error
-                    }
-                }
+        let tree = &mut self.tree;
+        match Tree::inode_mut(&mut tree.inodes, ino) { // find the file first
+            Some(Inode { attr, payload: Payload::File(fp), .. }) => {
+                let fp = File::make_mut(fp)?;
+                let size = fp.update_file(offset, data)?; // write the additional data to the file
+                attr.atime = ts; // update the timestamp
+                attr.mtime = ts;
+                tree.fs_size += fp.get_file_size() as i64 - attr.size as i64; // account only for the bytes the file grew by
+                attr.size = fp.get_file_size(); // update the new size to the file's attribute
+                Ok(size as u32)
             }
             // if file doesn't exist then throw error
-            None => reply.error(ENOENT), // No such file or directory error
-        }
-    }
-
-    /* This functions is there to read a file */
-    fn read(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, _size: u32, reply: ReplyData) {  
-        // similar to write(), but there is no updation only writing to the ReplyData 
-        match self.files.get_mut(&ino) {
-            Some(fp) => {
-                let mut thread_attrs = self.attrs.clone();
-                let thread_fp = fp.clone();
-                match thread_attrs.get_mut(&ino) {
-                    Some(attr) => {
-                        attr.atime = time::now().to_timespec();
-                        reply.data(&thread_fp.data[offset as usize..]);
-                    },
-                    None => {
-                        error!("read: cannot find ino: {}", ino);
-                        reply.error(ENOENT); // No such file or directory error
-                    },
-                }
+            _ => Err(ENOENT), // No such file or directory error
+        }
+    }
+
+    /* Makes sure the 'length' bytes from 'offset' are part of the file, like fallocate(2) without any mode:
+     * a file that ends before them is extended with zeros, nothing is ever cut off. Every byte of a file
+     * is backed by memory anyway, so growing it is all there is to do */
+    fn fallocate(&mut self, ino: u64, offset: u64, length: u64) -> Result<(), c_int> {
+        self.check_writable()?;
+        self.check_not_hidden(ino)?;
+        if length == 0 {
+            return Err(EINVAL);
+        }
+        let old_size = self.tree.get(ino).ok_or(ENOENT)?.file_or_err().map(File::get_file_size)?;
+        self.check_flags(ino, IMMUTABLE)?;
+        let new_size = offset.checked_add(length).ok_or(EFBIG)?;
+        if new_size <= old_size {
+            return Ok(());
+        }
+        self.check_file_size(new_size)?;
+        self.check_size_limit(old_size, new_size)?;
+        let tree = &mut self.tree;
+        if let Some(Inode { attr, payload: Payload::File(file), .. }) = Tree::inode_mut(&mut tree.inodes, ino) {
+            File::make_mut(file)?.truncate_bytes(new_size)?;
+            tree.fs_size += (new_size - old_size) as i64;
+            attr.size = new_size;
+        }
+        Ok(())
+    }
+
+    /* This functions is there to read a file through the handle 'fh', at most 'size' bytes starting from 'offset' */
+    fn read(&self, ino: u64, fh: u64, offset: i64, size: u32) -> Result<Cow<'_, [u8]>, c_int> {
+        let data = if snapshot::owns(ino) {
+            Cow::Borrowed(self.snapshot_read(ino)?)
+        }
+        else if introspect::owns(ino) {
+            self.introspect_read(ino, fh)?
+        }
+        else {
+            // similar to write(), but there is no updation only handing back the data
+            match self.tree.get(ino) {
+                Some(inode) => Cow::Borrowed(&inode.file_or_err()?.data[..]),
+                None => return Err(ENOENT), // No such file or directory error
             }
+        };
+        // Reading at or past the end of the file gives back nothing rather than an error
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(size as usize).min(data.len());
+        Ok(match data {
+            Cow::Borrowed(data) => Cow::Borrowed(&data[start..end]),
+            Cow::Owned(data) => Cow::Owned(data[start..end].to_vec()),
+        })
+    }
+
+    /* This function is to rename a file or directory in the FS */
+    fn rename(&mut self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr) -> Result<(), c_int> {
+        self.check_writable()?;
+        self.check_not_hidden_entry(parent, name)?;
+        self.check_not_hidden_entry(newparent, newname)?;
+        check_old_name(name)?;
+        check_old_name(newname)?;
+        check_hidden_name(newparent, newname)?;
+        // Both parents have to be directories
+        for dir in [parent, newparent] {
+            self.tree.get(dir).ok_or(ENOENT)?.entries().ok_or(ENOTDIR)?;
+        }
+        let file_ino = match self.entry(parent, name) {
+            Some(ino) => ino,
             None => {
-                reply.error(ENOENT); // No such file or directory error
+                error!("rename: {} does not exist", self.entry_path(parent, name));
+                return Err(ENOENT); // No such file or directory error
+            }
+        };
+        // Renaming something onto itself, or onto another link to it, leaves everything as it is
+        let replaced = self.entry(newparent, newname);
+        if replaced == Some(file_ino) {
+            return Ok(());
+        }
+        // Protected inodes stay where they are, and so do the entries of protected directories
+        for ino in [file_ino, parent].into_iter().chain(replaced) {
+            self.check_flags(ino, IMMUTABLE | APPEND_ONLY)?;
+        }
+        self.check_flags(newparent, if replaced.is_some() { IMMUTABLE | APPEND_ONLY } else { IMMUTABLE })?;
+        let moved_dir = self.tree.get(file_ino).is_some_and(Inode::is_dir);
+        // Whatever already has the new name is replaced, as long as it is the same kind and not a directory with entries
+        if let Some(old) = replaced.and_then(|old| self.tree.get(old)) {
+            match (moved_dir, old.is_dir()) {
+                (true, false) => return Err(ENOTDIR),
+                (false, true) => return Err(EISDIR),
+                _ => {}
+            }
+            if old.entries().is_some_and(|entries| !entries.is_empty()) {
+                return Err(ENOTEMPTY);
             }
         }
+        // A directory cannot be moved inside itself
+        if moved_dir {
+            let mut ino = newparent;
+            while ino != 1 {
+                if ino == file_ino {
+                    return Err(EINVAL);
+                }
+                ino = self.tree.get(ino).map_or(1, |inode| inode.root);
+            }
+        }
+        // remove the older version of the file and its existence
+        self.tree.unlink(parent, name);
+        match replaced.and_then(|old| self.tree.get(old)) {
+            Some(old) if old.is_dir() => {
+                let old = old.attr.ino;
+                self.tree.unlink(newparent, newname);
+                self.tree.remove(old);
+            }
+            Some(_) => self.tree.unlink_file(newparent, newname), // which may still have other links
+            None => {}
+        }
+        // Update the new file name and its new inode value
+        self.tree.link(newparent, newname.to_os_string(), file_ino);
+        // The moved inode keeps its own name and parent link, which paths are rebuilt from
+        if let Some(inode) = Tree::inode_mut(&mut self.tree.inodes, file_ino) {
+            inode.name = newname.to_os_string();
+            inode.root = newparent;
+        }
+        Ok(())
     }
+}
 
-    /* This function is to rename a file or directory in the FS */
-    fn rename(&mut self, _req: &Request, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, reply: ReplyEmpty) {
-        // First find the file with the passed 'ino' value
-        if self.inodes.contains_key(&parent) && self.inodes.contains_key(&newparent) {
-            let file_ino;
-            match self.inodes.get_mut(&parent) {
-                Some(parent_ino) => { // remove the older version of the file and its existence
-                    if let Some(ino) = parent_ino.nodes.remove(&name.to_str().unwrap().to_string()) {
-                        file_ino = ino;
-                    } 
-                    else {
-                        error!("{} not found in parent {}", name.to_str().unwrap().to_string(), parent);
-                        reply.error(ENOENT); // No such file or directory error
-                        return;
-                    }
-                }
-                None => {
-                    error!("rename: cannot find parent: {}", parent);
-                    reply.error(EINVAL);
-                    return;
-                }
+fn timespec(duration: Duration) -> Timespec {
+    Timespec::new(duration.as_secs() as i64, duration.subsec_nanos() as i32)
+}
+
+/* The entry a lookup answers with to have the kernel remember that a name is missing. Inode 0 means no inode */
+fn negative_entry() -> FileAttr {
+    let never = Timespec::new(0, 0);
+    FileAttr { ino: 0, size: 0, blocks: 0, atime: never, mtime: never, ctime: never, crtime: never, kind: FileType::RegularFile,
+        perm: 0, nlink: 0, uid: 0, gid: 0, rdev: 0, flags: 0 }
+}
+
+/* Checks a name a new directory entry is to be given. Names are any bytes but '/' and NUL, up to NAME_MAX
+ * of them, and '.' and '..' are always taken */
+fn check_name(name: &OsStr) -> Result<(), c_int> {
+    if name.len() > NAME_MAX {
+        return Err(ENAMETOOLONG);
+    }
+    if name.is_empty() || name.as_bytes().iter().any(|byte| *byte == b'/' || *byte == 0) {
+        return Err(EINVAL);
+    }
+    if name == "." || name == ".." {
+        return Err(EEXIST);
+    }
+    Ok(())
+}
+
+/* Fails with EEXIST for the names of the hidden directories, which always exist whether listed or not */
+fn check_hidden_name(parent: u64, name: &OsStr) -> Result<(), c_int> {
+    if (parent == 1 && name == SNAPSHOT_DIR) || introspect::is_dir_entry(parent, name) {
+        return Err(EEXIST);
+    }
+    Ok(())
+}
+
+/* Checks the name of an entry about to be removed or renamed, which '.' and '..' can never be */
+fn check_old_name(name: &OsStr) -> Result<(), c_int> {
+    match check_name(name) {
+        Err(EEXIST) => Err(EINVAL),
+        result => result,
+    }
+}
+
+// Out of all the function that the fuse::FileSystem implements there are handful of them which need tweaking
+// Every handler takes the lock, runs the matching operation on the shared state and turns the result into a reply
+impl Filesystem for RamFS {
+
+    fn destroy(&mut self, _req: &Request) {
+        self.shutdown();
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match self.op("getattr", ino, |fs| fs.getattr(ino).map(|attr| (fs.attr_ttl(), attr))) {
+            Ok((ttl, attr)) => reply.attr(&ttl, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn setattr(&mut self, req: &Request, ino: u64, _mode: Option<u32>, uid: Option<u32>, gid: Option<u32>, size: Option<u64>, atime: Option<Timespec>, mtime: Option<Timespec>, _fh: Option<u64>, crtime: Option<Timespec>, _chgtime: Option<Timespec>, _bkuptime: Option<Timespec>, flags: Option<u32>, reply: ReplyAttr) {
+        let changes = SetAttr { uid, gid, size, atime, mtime, crtime };
+        let result = self.audited(AuditEntry::new(req, "setattr", ino, None), |fs| {
+            // Only macOS sends chflags(2) this way, Linux keeps chattr to an ioctl FUSE never passes on.
+            // Flags go first, so clearing IMMUTABLE lets the other changes of the same call through
+            if let Some(flags) = flags {
+                fs.set_flags(ino, flags, req.uid())?;
+            }
+            fs.setattr(ino, changes).map(|attr| (fs.attr_ttl(), attr))
+        });
+        match result {
+            Ok((ttl, attr)) => reply.attr(&ttl, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        // Entries go from the tree straight into the reply, under the lock, until it is full. The offset the
+        // kernel comes back with is the cookie of the last entry it got
+        let _ = self.op("readdir", ino, |fs| {
+            let result = fs.readdir(ino, offset, &mut |ino, cookie, kind, name| reply.add(ino, cookie, kind, name));
+            match result {
+                Ok(()) => reply.ok(),
+                Err(err) => reply.error(err),
+            }
+            result
+        });
+    }
+
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let mut negative_ttl = None;
+        let result = self.op("lookup", parent, |fs| {
+            negative_ttl = fs.negative_ttl();
+            fs.lookup(parent, name).map(|attr| (fs.entry_ttl(), attr))
+        });
+        match (result, negative_ttl) {
+            (Ok((ttl, attr)), _) => reply.entry(&ttl, &attr, 0),
+            // A missing name is answered with inode 0 and a timeout, so the kernel stops asking for a while
+            (Err(ENOENT), Some(ttl)) => reply.entry(&ttl, &negative_entry(), 0),
+            (Err(err), _) => reply.error(err),
+        }
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.audited(AuditEntry::new(req, "rmdir", parent, Some(name)), |fs| fs.rmdir(parent, name)) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn mkdir(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
+        let new = NewAttr::from_request(req, mode);
+        match self.audited(AuditEntry::new(req, "mkdir", parent, Some(name)), |fs| fs.mkdir(parent, name, new).map(|attr| (fs.entry_ttl(), attr))) {
+            Ok((ttl, attr)) => reply.entry(&ttl, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    /* This function to open a file similar to 'touch' command */
+    fn open(&mut self, _req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        match self.op("open", ino, |fs| fs.open(ino, flags)) {
+            Ok((fh, flags)) => reply.opened(fh, flags),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn release(&mut self, _req: &Request, ino: u64, fh: u64, _flags: u32, _lock_owner: u64, _flush: bool, reply: ReplyEmpty) {
+        let _ = self.op("release", ino, |fs| {
+            fs.release(ino, fh);
+            Ok(())
+        });
+        reply.ok();
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.audited(AuditEntry::new(req, "unlink", parent, Some(name)), |fs| fs.unlink(parent, name)) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn create(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, flags: u32, reply: ReplyCreate) {
+        let new = NewAttr::from_request(req, mode);
+        let result = self.audited(AuditEntry::new(req, "create", parent, Some(name)), |fs| {
+            fs.create(parent, name, flags, new).map(|attr| (fs.entry_ttl(), attr, fs.handles.open(flags), fs.open_flags(attr.ino)))
+        });
+        match result {
+            Ok((ttl, attr, fh, open_flags)) => reply.created(&ttl, &attr, 0, fh, open_flags),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn write(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, data: &[u8], _flags: u32, reply: ReplyWrite) {
+        let result = self.audited(AuditEntry::new(req, "write", ino, None), |fs| {
+            fs.write_handle(ino, fh, offset, data)
+        });
+        match result {
+            Ok(size) => {
+                self.metrics.add_written(size as usize);
+                reply.written(size)
             }
-            // Update the new file name and its new inode value
-            if let Some(newparent_ino) = self.inodes.get_mut(&newparent) {
-                newparent_ino.nodes.insert(newname.to_str().unwrap().to_string(), file_ino);
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn read(&mut self, _req: &Request, ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
+        // The data is borrowed from the tree so the reply has to go out under the lock, from inside 'op'.
+        // Should the read panic, the reply is dropped unsent, which answers EIO
+        let _ = self.op("read", ino, |fs| {
+            let result = fs.read(ino, fh, offset, size);
+            match &result {
+                Ok(data) => {
+                    self.metrics.add_read(data.len());
+                    reply.data(data)
+                }
+                Err(err) => reply.error(*err),
             }
+            result.map(|_| ())
+        });
+    }
+
+    fn link(&mut self, req: &Request, ino: u64, newparent: u64, newname: &OsStr, reply: ReplyEntry) {
+        let entry = AuditEntry { to: Some((newparent, newname)), ..AuditEntry::new(req, "link", ino, None) };
+        match self.audited(entry, |fs| fs.link(ino, newparent, newname).map(|attr| (fs.entry_ttl(), attr))) {
+            Ok((ttl, attr)) => reply.entry(&ttl, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rename(&mut self, req: &Request, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, reply: ReplyEmpty) {
+        let entry = AuditEntry { to: Some((newparent, newname)), ..AuditEntry::new(req, "rename", parent, Some(name)) };
+        match self.audited(entry, |fs| fs.rename(parent, name, newparent, newname)) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn panic_answers_eio() {
+        let fs = RamFS::with_config(Config { read_only_on_panic: true, ..Config::default() });
+        let result: Result<(), c_int> = fs.op("mkdir", 1, |_| panic!("bug"));
+        assert_eq!(result, Err(EIO));
+        assert!(fs.metrics().contains("ramfs_panics_total{op=\"mkdir\"} 1"));
+        // errors=remount-ro turns the file-system read-only, and it keeps answering
+        assert!(fs.is_read_only());
+        assert_eq!(fs.op("getattr", 1, |fs| fs.getattr(1)).map(|attr| attr.ino), Ok(1));
+        assert_eq!(fs.mkdir("/dir").unwrap_err().raw_os_error(), Some(EROFS));
+    }
+
+    #[test]
+    fn panic_keeps_writable_by_default() {
+        let fs = RamFS::new();
+        let result: Result<(), c_int> = fs.op("write", 1, |_| panic!("bug"));
+        assert_eq!(result, Err(EIO));
+        assert!(!fs.is_read_only());
+        fs.mkdir("/dir").unwrap();
+    }
+
+    #[test]
+    fn failed_check_answers_eio() {
+        if !cfg!(debug_assertions) {
+            return; // Only debug builds check the tree after every operation
+        }
+        let fs = RamFS::new();
+        let result = fs.op("setattr", 1, |fs| {
+            fs.tree.fs_size += 1; // Counts a byte no file holds
+            Ok(())
+        });
+        assert_eq!(result, Err(EIO));
+        assert!(fs.metrics().contains("ramfs_panics_total{op=\"setattr\"} 1"));
+    }
+
+    #[test]
+    fn lock_deadlock() {
+        let fs = RamFS::new();
+        fs.write("/file", b"0123456789").unwrap();
+        let a = RamFile::open(&fs, "/file").unwrap();
+        let b = RamFile::open(&fs, "/file").unwrap();
+        a.lock_range(0, 1, true).unwrap();
+        b.lock_range(1, 1, true).unwrap();
+        let waiter = thread::spawn(move || {
+            b.lock_range(0, 1, true).unwrap();
+            b
+        });
+        // The circle is only closed once the waiter is parked
+        while fs.lock().locks.waiting() == 0 && !waiter.is_finished() {
+            thread::yield_now();
         }
-        reply.ok(); // reply to a request with nothing
+        assert_eq!(a.lock_range(1, 1, true).unwrap_err().raw_os_error(), Some(libc::EDEADLK));
+        a.unlock_range(0, 1).unwrap();
+        waiter.join().unwrap();
+    }
+
+    fn contents(fs: &RamFS, path: &str) -> Vec<u8> {
+        fs.read(path).unwrap()
+    }
+
+    #[test]
+    fn create_truncates() {
+        let fs = RamFS::new();
+        fs.write("/file", b"data").unwrap();
+        let mut inner = fs.lock();
+        let new = inner.new_attr(0o666);
+        let name = OsStr::new("file");
+        assert_eq!(inner.create(1, name, libc::O_RDWR as u32, new).map(|attr| attr.size), Ok(4));
+        assert_eq!(inner.create(1, name, (libc::O_RDWR | O_EXCL | O_TRUNC) as u32, new).err(), Some(EEXIST));
+        assert_eq!(inner.create(1, name, (libc::O_RDWR | O_TRUNC) as u32, new).map(|attr| attr.size), Ok(0));
+        drop(inner);
+        assert_eq!(contents(&fs, "/file"), b"");
+    }
+
+    #[test]
+    fn open_truncates() {
+        let fs = RamFS::new();
+        fs.write("/file", b"data").unwrap();
+        let ino = fs.stat("/file").unwrap().ino;
+        fs.lock().open(ino, O_RDONLY as u32).unwrap();
+        assert_eq!(contents(&fs, "/file"), b"data");
+        fs.lock().open(ino, (libc::O_WRONLY | O_TRUNC) as u32).unwrap();
+        assert_eq!(contents(&fs, "/file"), b"");
+        // A read-only file-system keeps its files whole
+        fs.write("/file", b"data").unwrap();
+        fs.set_read_only(true);
+        assert_eq!(fs.lock().open(ino, (O_RDONLY | O_TRUNC) as u32).err(), Some(EROFS));
+        assert_eq!(contents(&fs, "/file"), b"data");
+    }
+
+    #[test]
+    fn append_writes_at_eof() {
+        let fs = RamFS::new();
+        let ino = fs.create("/file").unwrap().ino;
+        let mut inner = fs.lock();
+        let (plain, _) = inner.open(ino, libc::O_WRONLY as u32).unwrap();
+        let (append, _) = inner.open(ino, (libc::O_WRONLY | O_APPEND) as u32).unwrap();
+        inner.write_handle(ino, plain, 0, b"abc").unwrap();
+        // The kernel's idea of the end is stale, the write still lands after everything written so far
+        inner.write_handle(ino, append, 0, b"de").unwrap();
+        inner.write_handle(ino, plain, 8, b"!").unwrap();
+        inner.write_handle(ino, append, 5, b"fg").unwrap();
+        drop(inner);
+        assert_eq!(contents(&fs, "/file"), b"abcde\0\0\0!fg");
+    }
+
+    fn cached(entry_ttl: f64, attr_ttl: f64) -> RamFS {
+        RamFS::with_config(Config {
+            entry_ttl: Duration::from_secs_f64(entry_ttl),
+            attr_ttl: Duration::from_secs_f64(attr_ttl),
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn timeouts() {
+        let fs = cached(0.5, 60.0);
+        assert_eq!(fs.lock().entry_ttl(), Timespec::new(0, 500_000_000));
+        assert_eq!(fs.lock().attr_ttl(), Timespec::new(60, 0));
+        // An entry reply carries the attributes too, which are never kept for longer than attr_timeout
+        let fs = cached(60.0, 2.0);
+        assert_eq!(fs.lock().entry_ttl(), Timespec::new(2, 0));
+        assert_eq!(fs.lock().attr_ttl(), Timespec::new(2, 0));
+    }
+
+    #[test]
+    fn negative_entries() {
+        assert_eq!(RamFS::new().lock().negative_ttl(), None);
+        let fs = RamFS::with_config(Config { negative_ttl: Some(Duration::from_secs(30)), ..Config::default() });
+        assert_eq!(fs.lock().negative_ttl(), Some(Timespec::new(30, 0)));
+        assert_eq!(fs.lock().lookup(1, OsStr::new("missing")).err(), Some(ENOENT));
+        // The kernel takes inode 0 for a name known not to exist
+        assert_eq!(negative_entry().ino, 0);
+    }
+
+    #[test]
+    fn open_flags() {
+        let flags = |config: Config| {
+            let fs = RamFS::with_config(config);
+            fs.write("/file", b"").unwrap();
+            let ino = fs.stat("/file").unwrap().ino;
+            let stats = fs.stat("/.ramfs/stats").unwrap().ino;
+            let mut fs = fs.lock();
+            (fs.open(ino, O_RDONLY as u32).unwrap().1, fs.open_flags(stats))
+        };
+        assert_eq!(flags(Config::default()), (0, FOPEN_DIRECT_IO));
+        assert_eq!(flags(Config { keep_cache: true, ..Config::default() }), (FOPEN_KEEP_CACHE, FOPEN_DIRECT_IO));
+        assert_eq!(flags(Config { direct_io: true, keep_cache: true, ..Config::default() }), (FOPEN_DIRECT_IO, FOPEN_DIRECT_IO));
     }
 }
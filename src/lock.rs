@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+use libc::{c_int, EAGAIN, EBADF, EDEADLK, EINVAL, F_RDLCK, F_UNLCK, F_WRLCK};
+
+/* A lock on the bytes 'start' to 'end' of a file, both included. 'typ' is F_RDLCK, F_WRLCK or F_UNLCK */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Lock {
+    pub start: u64,
+    pub end: u64,
+    pub typ: c_int,
+}
+
+impl Lock {
+    /* A lock on the whole file, the only kind flock(2) takes */
+    pub fn whole(typ: c_int) -> Lock {
+        Lock { start: 0, end: u64::MAX, typ }
+    }
+
+    fn overlaps(&self, other: &Lock) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    // Ranges of the same owner that touch end to end are merged as well
+    fn touches(&self, other: &Lock) -> bool {
+        self.start <= other.end.saturating_add(1) && other.start <= self.end.saturating_add(1)
+    }
+}
+
+/* Who a lock belongs to, always the open file it was taken through. Record locks behave like the open
+ * file description locks of F_OFD_SETLK. flock(2) locks live apart from record locks, neither kind ever
+ * gets in the way of the other. The kernel only hands locks taken on a mount to a FUSE file-system that
+ * asks for them at mount time, which the 'fuse' crate does not do, so all of these come from RamFile */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Owner {
+    Handle(u64),
+    Flock(u64),
+}
+
+impl Owner {
+    fn conflicts(&self, other: &Owner) -> bool {
+        *self != *other && matches!(self, Owner::Flock(_)) == matches!(other, Owner::Flock(_))
+    }
+
+    // The handle the lock goes away with when it is closed
+    fn handle(&self) -> u64 {
+        match *self {
+            Owner::Handle(fh) | Owner::Flock(fh) => fh,
+        }
+    }
+}
+
+/* Called with the outcome of a wait, once the lock was granted or the wait given up on */
+pub(crate) type Wake = Box<dyn FnOnce(Result<(), c_int>) + Send>;
+
+struct Held {
+    owner: Owner,
+    lock: Lock,
+}
+
+struct Waiter {
+    ino: u64,
+    owner: Owner,
+    lock: Lock,
+    wake: Wake,
+}
+
+/* The locks held on every inode, and the requests waiting for one. Nothing here ever blocks: a waiter is
+ * parked with whatever wakes it, and woken from whichever call frees up its range */
+#[derive(Default)]
+pub(crate) struct Locks {
+    held: HashMap<u64, Vec<Held>>,
+    waiting: Vec<Waiter>,
+}
+
+/* Turns away malformed lock requests */
+pub(crate) fn check(lock: &Lock) -> Result<(), c_int> {
+    if ![F_RDLCK, F_WRLCK, F_UNLCK].contains(&lock.typ) || lock.start > lock.end {
+        return Err(EINVAL);
+    }
+    Ok(())
+}
+
+impl Locks {
+    // The locks of other owners standing in the way of 'owner' taking 'lock'
+    fn conflicts<'a>(&'a self, ino: u64, owner: Owner, lock: &'a Lock) -> impl Iterator<Item = &'a Held> {
+        self.held.get(&ino).into_iter().flatten().filter(move |held| {
+            held.owner.conflicts(&owner) && held.lock.overlaps(lock) && (held.lock.typ == F_WRLCK || lock.typ == F_WRLCK)
+        })
+    }
+
+    /* Returns the first lock that would stop 'owner' from taking 'lock' */
+    pub fn test(&self, ino: u64, owner: Owner, lock: &Lock) -> Option<Lock> {
+        if lock.typ == F_UNLCK {
+            return None;
+        }
+        self.conflicts(ino, owner, lock).map(|held| held.lock).next()
+    }
+
+    /* Takes, changes or drops (F_UNLCK) the range of 'lock' for 'owner', failing with EAGAIN if another
+     * owner holds a lock in the way. The owner's locks in the range are replaced, splitting those that
+     * stick out of it, and a new lock absorbs the owner's locks of the same type it overlaps or touches */
+    pub fn set(&mut self, ino: u64, owner: Owner, lock: Lock) -> Result<(), c_int> {
+        if self.test(ino, owner, &lock).is_some() {
+            return Err(EAGAIN);
+        }
+        self.replace(ino, owner, lock);
+        self.wake(ino);
+        Ok(())
+    }
+
+    fn replace(&mut self, ino: u64, owner: Owner, lock: Lock) {
+        let held = self.held.entry(ino).or_default();
+        let mut new = lock;
+        let mut kept = Vec::with_capacity(held.len() + 1);
+        for other in held.drain(..) {
+            if other.owner != owner || !other.lock.touches(&new) {
+                kept.push(other);
+            } else if other.lock.typ == new.typ && new.typ != F_UNLCK {
+                new.start = new.start.min(other.lock.start);
+                new.end = new.end.max(other.lock.end);
+            } else if !other.lock.overlaps(&new) {
+                kept.push(other);
+            } else {
+                if other.lock.start < new.start {
+                    kept.push(Held { owner, lock: Lock { end: new.start - 1, ..other.lock } });
+                }
+                if other.lock.end > new.end {
+                    kept.push(Held { owner, lock: Lock { start: new.end + 1, ..other.lock } });
+                }
+            }
+        }
+        if new.typ != F_UNLCK {
+            kept.push(Held { owner, lock: new });
+        }
+        kept.sort_by_key(|held| held.lock.start);
+        if kept.is_empty() {
+            self.held.remove(&ino);
+        } else {
+            *held = kept;
+        }
+    }
+
+    /* Parks a request that 'set' turned away until it can be granted, which the Wake 'parked' returns is
+     * told about. Fails with EDEADLK instead, without calling 'parked', if one of the owners in the way is
+     * itself waiting, directly or through others, for a lock 'owner' holds */
+    pub fn wait(&mut self, ino: u64, owner: Owner, lock: Lock, parked: impl FnOnce() -> Wake) -> Result<(), c_int> {
+        if self.deadlock(ino, owner, &lock) {
+            return Err(EDEADLK);
+        }
+        self.waiting.push(Waiter { ino, owner, lock, wake: parked() });
+        Ok(())
+    }
+
+    /* How many requests are parked, for tests to know when a waiter got as far as 'wait' */
+    #[cfg(test)]
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    fn deadlock(&self, ino: u64, owner: Owner, lock: &Lock) -> bool {
+        let mut blockers: Vec<Owner> = self.conflicts(ino, owner, lock).map(|held| held.owner).collect();
+        let mut seen = HashSet::new();
+        while let Some(blocker) = blockers.pop() {
+            if blocker == owner {
+                return true;
+            }
+            if seen.insert(blocker) {
+                for waiter in self.waiting.iter().filter(|waiter| waiter.owner == blocker) {
+                    blockers.extend(self.conflicts(waiter.ino, waiter.owner, &waiter.lock).map(|held| held.owner));
+                }
+            }
+        }
+        false
+    }
+
+    // Grants whatever waits on 'ino' and is no longer in anyone's way, oldest first
+    fn wake(&mut self, ino: u64) {
+        let mut i = 0;
+        while i < self.waiting.len() {
+            let waiter = &self.waiting[i];
+            if waiter.ino == ino && self.test(ino, waiter.owner, &waiter.lock).is_none() {
+                let waiter = self.waiting.remove(i);
+                self.replace(ino, waiter.owner, waiter.lock);
+                (waiter.wake)(Ok(()));
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /* Drops the locks of the handle 'fh' of 'ino' when it is closed. Waits on the handle end in EBADF */
+    pub fn release(&mut self, ino: u64, fh: u64) {
+        let gone = |owner: &Owner| owner.handle() == fh;
+        let mut i = 0;
+        while i < self.waiting.len() {
+            if self.waiting[i].ino == ino && gone(&self.waiting[i].owner) {
+                (self.waiting.remove(i).wake)(Err(EBADF));
+            } else {
+                i += 1;
+            }
+        }
+        if let Some(held) = self.held.get_mut(&ino) {
+            held.retain(|held| !gone(&held.owner));
+            if held.is_empty() {
+                self.held.remove(&ino);
+            }
+        }
+        self.wake(ino);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> Lock {
+        Lock { start, end, typ: F_WRLCK }
+    }
+
+    #[test]
+    fn deadlock_through_a_chain() {
+        let mut locks = Locks::default();
+        let (a, b, c) = (Owner::Handle(1), Owner::Handle(2), Owner::Handle(3));
+        for (owner, byte) in [(a, 0), (b, 1), (c, 2)] {
+            locks.set(1, owner, range(byte, byte)).unwrap();
+        }
+        // a waits for b, b for c: c waiting for a would close the circle
+        locks.wait(1, a, range(1, 1), || Box::new(|_| {})).unwrap();
+        locks.wait(1, b, range(2, 2), || Box::new(|_| {})).unwrap();
+        assert_eq!(locks.wait(1, c, range(0, 0), || panic!("parked")), Err(EDEADLK));
+        assert_eq!(locks.waiting(), 2);
+        // Closing c grants b, which still waits on nothing a holds
+        locks.release(1, 3);
+        assert_eq!(locks.waiting(), 1);
+        assert_eq!(locks.test(1, a, &range(2, 2)).map(|lock| lock.start), Some(1));
+    }
+}
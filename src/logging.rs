@@ -0,0 +1,51 @@
+use std::sync::{OnceLock, RwLock};
+use log::{Log, Metadata, Record, SetLoggerError};
+
+/* An env_logger whose filter can be swapped for another one while the program keeps running */
+struct Reloadable {
+    current: RwLock<env_logger::Logger>,
+}
+
+impl Log for Reloadable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.current.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.current.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.current.read().unwrap().flush()
+    }
+}
+
+static LOGGER: OnceLock<Reloadable> = OnceLock::new();
+
+/* Builds a logger from RUST_LOG, with 'filter' (same syntax) applied on top of it */
+fn build(filter: Option<&str>) -> env_logger::Logger {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(filter) = filter {
+        builder.parse_filters(filter);
+    }
+    builder.build()
+}
+
+/* Installs the process wide logger, the filter can be changed later with set_filter */
+pub fn init(filter: Option<&str>) -> Result<(), SetLoggerError> {
+    let logger = build(filter);
+    let max_level = logger.filter();
+    let reloadable = LOGGER.get_or_init(|| Reloadable { current: RwLock::new(logger) });
+    log::set_logger(reloadable)?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/* Replaces the filter of the logger installed by init, e.g. with 'debug' or 'ramfs=trace' */
+pub fn set_filter(filter: &str) -> Result<(), String> {
+    let reloadable = LOGGER.get().ok_or("the logger was not installed by ramfs")?;
+    let logger = env_logger::Builder::new().parse_filters(filter).build(); // RUST_LOG no longer applies
+    log::set_max_level(logger.filter());
+    *reloadable.current.write().unwrap() = logger;
+    Ok(())
+}
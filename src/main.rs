@@ -8,26 +8,250 @@ extern crate env_logger;
 
 // Bindings
 use ramfs::RamFS;
+use ramfs::{control, logging};
+use ramfs::options::{self, Command};
+use libc::{c_char, c_int};
 use std::env;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::process;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
 fn main() {
-    // Init log level system (error, warn, info, debug, trace) for this program
-    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    let program = args.first().cloned().unwrap_or_else(|| "ramfs".to_string());
 
-    // Create a file system instance
-    let fs = RamFS::new();
-
-    /* Extract the mountpoint from the command line argument
-     * If the argument is not found generate an error and return
+    /* Parse the command line, on any mistake print what went wrong along with a hint and exit
     */
-    let mountpoint = match env::args().nth(1) {
-        Some(path) => path,
-        None => {
-            error!("Usage: {} <mount_point>. Provide mountpoint argument", env::args().nth(0).unwrap());
+    let opts = match options::parse(args) {
+        Ok(Command::Mount(opts)) => *opts,
+        Ok(Command::Help) => {
+            print!("{}", options::usage(&program));
             return;
         }
+        Err(msg) => {
+            eprintln!("{}: {}\nTry '{} --help' for more information.", program, msg, program);
+            process::exit(1);
+        }
+    };
+
+    // Init log level system (error, warn, info, debug, trace) for this program, RUST_LOG is used unless --log-level is given
+    if let Err(err) = logging::init(opts.log_level.as_deref()) {
+        eprintln!("{}: cannot set up logging: {}", program, err);
+    }
+
+    // Create a file system instance, with the root directory and limits asked for on the command line
+    let fs = RamFS::with_config(opts.config());
+    if let Some(path) = &opts.audit_log {
+        if let Err(err) = fs.set_audit_log(path, opts.audit_log_size) {
+            error!("cannot open audit log {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+
+    // Resolve the mountpoint now, the daemon below no longer runs from the current directory
+    let mountpoint = match fs::canonicalize(&opts.mountpoint) {
+        Ok(path) => path,
+        Err(err) => {
+            error!("cannot use mountpoint {}: {}", opts.mountpoint.display(), err);
+            process::exit(1);
+        }
+    };
+
+    // The pidfile is removed on exit, by which time the daemon runs from '/'
+    let pidfile = match opts.pidfile.as_deref().map(std::path::absolute).transpose() {
+        Ok(path) => path,
+        Err(err) => {
+            error!("cannot use pidfile: {}", err);
+            process::exit(1);
+        }
+    };
+
+    // Every thread started from here on inherits this mask, so the signals are only ever picked up below
+    let signals = block_signals();
+
+    // Go to the background before mounting, while there is still only one thread to fork. The parent
+    // waits around until the child says whether the mount worked
+    let parent_pipe = if opts.foreground { None } else { Some(daemonize()) };
+
+    // Mount the file system using fuse's session api, so a failure can be reported instead of panicking
+    let fuse_args = opts.fuse_args();
+    let fuse_args: Vec<&OsStr> = fuse_args.iter().map(|arg| arg.as_os_str()).collect();
+    let mut session = match fuse::Session::new(fs.clone(), &mountpoint, &fuse_args) {
+        Ok(session) => session,
+        Err(err) => {
+            error!("failed to mount {}: {}", mountpoint.display(), err);
+            process::exit(1);
+        }
     };
 
-    // Mount the file system using fuse's mount api
-    fuse::mount(fs, &mountpoint, &[]).unwrap();
-}
\ No newline at end of file
+    // The mount is in place, let whoever started us know
+    if let Some(path) = &pidfile {
+        if let Err(err) = fs::write(path, format!("{}\n", process::id())) {
+            error!("cannot write pidfile {}: {}", path.display(), err);
+        }
+    }
+    if let Some(fd) = opts.ready_fd {
+        let mut ready = unsafe { fs::File::from_raw_fd(fd) };
+        if let Err(err) = ready.write_all(b"READY=1\n") {
+            error!("cannot report readiness on fd {}: {}", fd, err);
+        }
+    }
+    let control = opts.control_socket.as_ref().and_then(|path| match control::serve(fs.clone(), path) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            error!("cannot serve control socket {}: {}", path.display(), err);
+            None
+        }
+    });
+    if let Some(addr) = &opts.metrics_addr {
+        if let Err(err) = ramfs::serve_metrics(fs.clone(), addr.as_str()) {
+            error!("cannot serve metrics on {}: {}", addr, err);
+        }
+    }
+    if let Some(mut pipe) = parent_pipe {
+        detach_stdio();
+        let _ = pipe.write_all(&[0]);
+    }
+
+    // Serve requests on a thread of their own, the main thread is left waiting for a signal
+    let finished = Arc::new(AtomicBool::new(false));
+    let wake = WakeOnDrop(finished.clone());
+    let runner = thread::spawn(move || {
+        // Whether the loop ends because of an unmount or a panic, wake up the main thread
+        let _wake = wake;
+        session.run()
+    });
+    let signal = wait_for_signal(&signals);
+    // Once the session is over the file-system is already unmounted, whatever the signal was
+    if !finished.load(Ordering::SeqCst) {
+        info!("received signal {}, unmounting {}", signal, mountpoint.display());
+        unmount(&mountpoint);
+    }
+
+    let status = match runner.join() {
+        Ok(Ok(())) => 0,
+        Ok(Err(err)) => {
+            error!("file-system session failed: {}", err);
+            1
+        }
+        Err(_) => {
+            error!("file-system session panicked");
+            1
+        }
+    };
+    fs.shutdown();
+    drop(control);
+    if let Some(path) = &pidfile {
+        let _ = fs::remove_file(path);
+    }
+    process::exit(status);
+}
+
+/* Forks, the parent only returns through process::exit once the child writes a byte to the pipe (the mount
+ * worked, exit 0) or closes it without writing (anything else, exit 1). The child gets the pipe back */
+fn daemonize() -> fs::File {
+    let mut fds: [c_int; 2] = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        error!("failed to daemonize: {}", io::Error::last_os_error());
+        process::exit(1);
+    }
+    match unsafe { libc::fork() } {
+        -1 => {
+            error!("failed to daemonize: {}", io::Error::last_os_error());
+            process::exit(1);
+        }
+        0 => unsafe {
+            libc::close(fds[0]);
+            libc::setsid();
+            fs::File::from_raw_fd(fds[1])
+        },
+        _ => {
+            let mut pipe = unsafe {
+                libc::close(fds[1]);
+                fs::File::from_raw_fd(fds[0])
+            };
+            let mut byte = [0];
+            match pipe.read(&mut byte) {
+                Ok(1) => process::exit(0),
+                _ => process::exit(1),
+            }
+        }
+    }
+}
+
+/* Points stdin, stdout and stderr at /dev/null and leaves the current directory, like daemon(3) */
+fn detach_stdio() {
+    unsafe {
+        libc::chdir(c"/".as_ptr());
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null >= 0 {
+            for fd in 0..3 {
+                libc::dup2(null, fd);
+            }
+            if null > 2 {
+                libc::close(null);
+            }
+        }
+    }
+}
+
+/* Sets its flag and sends SIGTERM to this process when dropped. The session thread holds one so that the
+ * main thread, sitting in sigwait, also notices when the file-system is unmounted from outside. The flag is
+ * set first, so by the time the signal arrives it tells that the session has already ended */
+struct WakeOnDrop(Arc<AtomicBool>);
+
+impl Drop for WakeOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+        unsafe { libc::kill(libc::getpid(), libc::SIGTERM); }
+    }
+}
+
+/* Blocks the signals that stop ramfs in the calling thread and returns them as a set for sigwait */
+fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::sigaddset(&mut set, *signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        set
+    }
+}
+
+/* Waits until one of the signals in 'set' arrives and returns it */
+fn wait_for_signal(set: &libc::sigset_t) -> c_int {
+    let mut signal: c_int = 0;
+    loop {
+        if unsafe { libc::sigwait(set, &mut signal) } == 0 {
+            return signal;
+        }
+    }
+}
+
+/* Detaches the file-system from the mountpoint. Root can do it directly, anyone else has to go through
+ * libfuse (and so fusermount), which is also what the fuse crate does when a session is dropped */
+fn unmount(mountpoint: &Path) {
+    extern "C" {
+        fn fuse_unmount_compat22(mountpoint: *const c_char);
+    }
+    let path = match CString::new(mountpoint.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    unsafe {
+        if libc::umount2(path.as_ptr(), libc::MNT_DETACH) != 0 {
+            fuse_unmount_compat22(path.as_ptr());
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use libc::c_int;
+use super::RamFS;
+
+// Upper bounds, in seconds, of the latency histogram buckets. An in-memory operation normally lands in
+// the first few, the slow end is there to catch lock contention
+const BUCKETS: [f64; 12] = [0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5,
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.5];
+
+/* Counters kept for each kind of FUSE callback */
+#[derive(Default)]
+struct OpStats {
+    ok: u64,
+    errors: BTreeMap<c_int, u64>,
+    buckets: [u64; BUCKETS.len()], // per bucket, not cumulative, that is done when rendering
+    count: u64,
+    seconds: f64,
+}
+
+#[derive(Default)]
+struct Counters {
+    ops: BTreeMap<&'static str, OpStats>,
+    bytes_read: u64,
+    bytes_written: u64,
+    panics: BTreeMap<&'static str, u64>,
+}
+
+/* Per-operation metrics of a mount, shared by every clone of its RamFS */
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+/* Names the errno values the handlers hand back, anything else is shown as a number */
+pub(crate) fn errno_name(err: c_int) -> String {
+    let name = match err {
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        libc::EIO => "EIO",
+        libc::EBADF => "EBADF",
+        libc::EAGAIN => "EAGAIN",
+        libc::EACCES => "EACCES",
+        libc::EEXIST => "EEXIST",
+        libc::EXDEV => "EXDEV",
+        libc::ENOTDIR => "ENOTDIR",
+        libc::EISDIR => "EISDIR",
+        libc::EINVAL => "EINVAL",
+        libc::EFBIG => "EFBIG",
+        libc::ENOSPC => "ENOSPC",
+        libc::EROFS => "EROFS",
+        libc::EDEADLK => "EDEADLK",
+        libc::ENAMETOOLONG => "ENAMETOOLONG",
+        libc::ENOSYS => "ENOSYS",
+        libc::EOPNOTSUPP => "EOPNOTSUPP",
+        libc::ENOTEMPTY => "ENOTEMPTY",
+        _ => return format!("errno_{}", err),
+    };
+    name.to_string()
+}
+
+impl Metrics {
+    /* Counts one finished callback, 'error' is the errno it replied with if it failed */
+    pub(crate) fn record(&self, op: &'static str, elapsed: Duration, error: Option<c_int>) {
+        let mut counters = self.counters.lock().unwrap();
+        let stats = counters.ops.entry(op).or_default();
+        match error {
+            None => stats.ok += 1,
+            Some(err) => *stats.errors.entry(err).or_insert(0) += 1,
+        }
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+        stats.count += 1;
+        stats.seconds += seconds;
+    }
+
+    pub(crate) fn add_read(&self, bytes: usize) {
+        self.counters.lock().unwrap().bytes_read += bytes as u64;
+    }
+
+    pub(crate) fn add_written(&self, bytes: usize) {
+        self.counters.lock().unwrap().bytes_written += bytes as u64;
+    }
+
+    pub(crate) fn add_panic(&self, op: &'static str) {
+        *self.counters.lock().unwrap().panics.entry(op).or_insert(0) += 1;
+    }
+}
+
+impl RamFS {
+    /* Renders the metrics of this file-system in the Prometheus text exposition format. The gauges come
+     * from the tree, so a scrape waits for the operation in progress, if any, to finish */
+    pub fn metrics(&self) -> String {
+        let stats = self.stats(); // taken before the counters, the two locks are never held together
+        let counters = self.metrics.counters.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP ramfs_operations_total FUSE callbacks handled, by operation and result.\n");
+        out.push_str("# TYPE ramfs_operations_total counter\n");
+        for (op, op_stats) in &counters.ops {
+            let _ = writeln!(out, "ramfs_operations_total{{op=\"{}\",result=\"ok\"}} {}", op, op_stats.ok);
+            for (err, count) in &op_stats.errors {
+                let _ = writeln!(out, "ramfs_operations_total{{op=\"{}\",result=\"{}\"}} {}", op, errno_name(*err), count);
+            }
+        }
+
+        out.push_str("# HELP ramfs_operation_duration_seconds Time spent handling FUSE callbacks.\n");
+        out.push_str("# TYPE ramfs_operation_duration_seconds histogram\n");
+        for (op, op_stats) in &counters.ops {
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(op_stats.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(out, "ramfs_operation_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}", op, bound, cumulative);
+            }
+            let _ = writeln!(out, "ramfs_operation_duration_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}", op, op_stats.count);
+            let _ = writeln!(out, "ramfs_operation_duration_seconds_sum{{op=\"{}\"}} {}", op, op_stats.seconds);
+            let _ = writeln!(out, "ramfs_operation_duration_seconds_count{{op=\"{}\"}} {}", op, op_stats.count);
+        }
+
+        out.push_str("# HELP ramfs_panics_total FUSE callbacks that panicked and were answered with EIO.\n");
+        out.push_str("# TYPE ramfs_panics_total counter\n");
+        for (op, count) in &counters.panics {
+            let _ = writeln!(out, "ramfs_panics_total{{op=\"{}\"}} {}", op, count);
+        }
+
+        let counter = |out: &mut String, name: &str, help: &str, kind: &str, value: u64| {
+            let _ = write!(out, "# HELP {0} {1}\n# TYPE {0} {2}\n{0} {3}\n", name, help, kind, value);
+        };
+        counter(&mut out, "ramfs_read_bytes_total", "Bytes handed back by read.", "counter", counters.bytes_read);
+        counter(&mut out, "ramfs_written_bytes_total", "Bytes accepted by write.", "counter", counters.bytes_written);
+        counter(&mut out, "ramfs_inodes", "Inodes in the live tree.", "gauge", stats.inodes);
+        counter(&mut out, "ramfs_bytes", "Bytes of file data in the live tree.", "gauge", stats.bytes);
+        counter(&mut out, "ramfs_snapshots", "Snapshots being kept.", "gauge", stats.snapshots);
+        out
+    }
+}
+
+/* Serves the metrics of 'fs' over plain HTTP on 'addr', whatever path is asked for, for Prometheus to scrape */
+pub fn serve_http<A: ToSocketAddrs>(fs: RamFS, addr: A) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|mut stream| {
+                stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+                // Nothing in the request matters, only read past its headers
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line)? > 2 {
+                    line.clear();
+                }
+                let body = fs.metrics();
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body)
+            });
+            if let Err(err) = result {
+                warn!("metrics: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(metrics: &str, series: &str) -> u64 {
+        let line = metrics.lines().find(|line| line.strip_prefix(series).is_some_and(|rest| rest.starts_with(' ')));
+        line.unwrap_or_else(|| panic!("no {} in\n{}", series, metrics)).rsplit(' ').next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn rendering() {
+        let fs = RamFS::new();
+        fs.metrics.record("lookup", Duration::ZERO, None);
+        fs.metrics.record("lookup", Duration::from_micros(3), Some(libc::ENOENT));
+        fs.metrics.record("lookup", Duration::from_micros(3), Some(libc::ENOENT));
+        fs.metrics.record("lookup", Duration::from_secs(1), Some(9999)); // slower than the last bucket
+        let metrics = fs.metrics();
+        assert_eq!(value(&metrics, "ramfs_operations_total{op=\"lookup\",result=\"ok\"}"), 1);
+        assert_eq!(value(&metrics, "ramfs_operations_total{op=\"lookup\",result=\"ENOENT\"}"), 2);
+        assert_eq!(value(&metrics, "ramfs_operations_total{op=\"lookup\",result=\"errno_9999\"}"), 1);
+        // Buckets count everything up to their bound, only +Inf catches the slow one
+        let bucket = |le: &str| value(&metrics, &format!("ramfs_operation_duration_seconds_bucket{{op=\"lookup\",le=\"{}\"}}", le));
+        assert_eq!(bucket("0.000001"), 1);
+        assert_eq!(bucket("0.000005"), 3);
+        assert_eq!(bucket("0.5"), 3);
+        assert_eq!(bucket("+Inf"), 4);
+        assert_eq!(value(&metrics, "ramfs_operation_duration_seconds_count{op=\"lookup\"}"), bucket("+Inf"));
+        let mut last = 0;
+        for bound in BUCKETS {
+            assert!(bucket(&bound.to_string()) >= last);
+            last = bucket(&bound.to_string());
+        }
+        assert_eq!(value(&metrics, "ramfs_inodes"), 1);
+    }
+
+    #[test]
+    fn errno_names() {
+        assert_eq!(errno_name(libc::ENOENT), "ENOENT");
+        assert_eq!(errno_name(libc::ENOTEMPTY), "ENOTEMPTY");
+        assert_eq!(errno_name(-1), "errno_-1");
+    }
+}
@@ -0,0 +1,326 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use super::Config;
+
+/* Everything that can be set from the command line, either directly or through '-o' mount options */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub mountpoint: PathBuf,
+    pub fsname: Option<String>,
+    pub size: Option<u64>, // bytes
+    pub nr_inodes: Option<u64>,
+    pub max_file_size: Option<u64>, // bytes
+    pub mode: Option<u16>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub allow_other: bool,
+    pub read_only: bool,
+    pub read_only_on_panic: bool,
+    pub entry_timeout: Option<Duration>,
+    pub attr_timeout: Option<Duration>,
+    pub negative_timeout: Option<Duration>,
+    pub keep_cache: bool,
+    pub direct_io: bool,
+    pub foreground: bool,
+    pub log_level: Option<String>,
+    pub pidfile: Option<PathBuf>,
+    pub control_socket: Option<PathBuf>,
+    pub metrics_addr: Option<String>, // host:port to serve Prometheus metrics on
+    pub audit_log: Option<PathBuf>,
+    pub audit_log_size: u64, // bytes the audit log may grow to before it is rotated
+    pub ready_fd: Option<i32>, // written "READY=1\n" and closed once mounted, like sd_notify
+    pub kernel_opts: Vec<String>, // generic mount flags like 'nosuid' that are handed straight to the kernel
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Mount(Box<Options>),
+}
+
+// Generic mount flags the kernel understands for any FUSE mount
+const KERNEL_OPTS: &[&str] = &["nosuid", "suid", "nodev", "dev", "noexec", "exec",
+    "atime", "noatime", "relatime", "norelatime", "sync", "async", "dirsync", "default_permissions", "allow_root"];
+// Options mount(8) and fstab use for their own purposes, which mean nothing to the file-system
+const IGNORED_OPTS: &[&str] = &["defaults", "auto", "noauto", "user", "nouser", "users", "_netdev", "nofail"];
+
+/* Returns the help text, 'program' is the name the binary was run as */
+pub fn usage(program: &str) -> String {
+    format!("Usage: {0} [options] [source] <mountpoint>
+       mount.ramfs <source> <mountpoint> [-o options]
+
+Options:
+    -o opt[,opt...]      mount options, see below
+    -f, --foreground     stay in the foreground (the default unless run as mount.ramfs)
+    --daemon             detach once mounted, exiting 0 only if mounting worked
+    --pidfile PATH       write the pid of the running ramfs to PATH
+    --ready-fd N         write READY=1 to file descriptor N once mounted, then close it
+    --control PATH       serve control commands on a Unix socket at PATH, see ramfsctl
+    --metrics ADDR       serve Prometheus metrics over HTTP on ADDR, e.g. 127.0.0.1:9432
+    --audit-log PATH     record every change, with who made it, as JSON lines in PATH
+    --audit-log-size N[k|m|g]  rotate the audit log once it reaches N bytes (default: 16m)
+    --log-level FILTER   log filter in RUST_LOG syntax, e.g. 'debug' or 'ramfs=trace'
+    -h, --help           print this help
+
+Mount options:
+    size=N[k|m|g|t|%]    maximum bytes of file data, '%' is relative to physical memory
+    nr_inodes=N[k|m|g]   maximum number of inodes
+    max_file_size=N[k|m|g|t|%]  maximum bytes in a single file, writes past it fail with EFBIG
+    mode=OCTAL           permissions of the root directory
+    uid=N, gid=N         owner of the root directory
+    allow_other          let users other than the one mounting access the file-system
+    ro                   start read-only, changes fail with EROFS until switched back
+    errors=remount-ro    switch to read-only once an operation panics (default: errors=continue)
+    fsname=NAME          name shown as the source in /proc/mounts (default: ramfs)
+    entry_timeout=SECS   how long the kernel caches names it looked up, never longer than
+                         attr_timeout (default: 1)
+    attr_timeout=SECS    how long the kernel caches attributes (default: 1)
+    negative_timeout=SECS  how long the kernel remembers a name is missing (default: 0)
+    keep_cache           keep cached file contents across opens
+    direct_io            bypass the page cache for file contents
+
+To mount from /etc/fstab, link this binary as /sbin/mount.ramfs and add a line such as
+    ramfs  /mnt/scratch  ramfs  size=1g,mode=1777  0 0
+", program)
+}
+
+/* Parses a number with an optional binary unit suffix, e.g. '64k' or '2g' */
+pub(crate) fn parse_scaled(value: &str, is_size: bool) -> Result<u64, String> {
+    let (digits, scale) = match value.chars().last() {
+        Some('k') | Some('K') => (&value[..value.len() - 1], 1 << 10),
+        Some('m') | Some('M') => (&value[..value.len() - 1], 1 << 20),
+        Some('g') | Some('G') => (&value[..value.len() - 1], 1 << 30),
+        Some('t') | Some('T') if is_size => (&value[..value.len() - 1], 1 << 40),
+        Some('%') if is_size => {
+            let percent: u64 = value[..value.len() - 1].parse().map_err(|_| format!("invalid size '{}'", value))?;
+            return Ok(physical_memory() / 100 * percent);
+        }
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(scale))
+        .ok_or_else(|| format!("invalid number '{}'", value))
+}
+
+/* Parses a timeout in seconds, fractions like '0.5' included */
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value.parse::<f64>().ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("invalid timeout '{}'", value))
+}
+
+/* Returns the number of bytes of physical memory in this machine */
+fn physical_memory() -> u64 {
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    (pages.max(0) as u64).saturating_mul(page_size.max(0) as u64)
+}
+
+impl Options {
+    /* Applies one comma separated '-o' list on top of the current options */
+    fn apply_mount_opts(&mut self, list: &str) -> Result<(), String> {
+        for opt in list.split(',').filter(|opt| !opt.is_empty()) {
+            let (key, value) = match opt.find('=') {
+                Some(i) => (&opt[..i], Some(&opt[i + 1..])),
+                None => (opt, None),
+            };
+            match (key, value) {
+                ("size", Some(value)) => self.size = Some(parse_scaled(value, true)?),
+                ("nr_inodes", Some(value)) => self.nr_inodes = Some(parse_scaled(value, false)?),
+                ("max_file_size", Some(value)) => self.max_file_size = Some(parse_scaled(value, true)?),
+                ("mode", Some(value)) => match u16::from_str_radix(value, 8) {
+                    Ok(mode) if mode <= 0o7777 => self.mode = Some(mode),
+                    _ => return Err(format!("invalid mode '{}'", value)),
+                },
+                ("uid", Some(value)) => self.uid = Some(value.parse().map_err(|_| format!("invalid uid '{}'", value))?),
+                ("gid", Some(value)) => self.gid = Some(value.parse().map_err(|_| format!("invalid gid '{}'", value))?),
+                ("fsname", Some(value)) => self.fsname = Some(value.to_string()),
+                ("allow_other", None) => self.allow_other = true,
+                ("ro", None) => self.read_only = true,
+                ("rw", None) => self.read_only = false,
+                ("errors", Some("continue")) => self.read_only_on_panic = false,
+                ("errors", Some("remount-ro")) => self.read_only_on_panic = true,
+                ("entry_timeout", Some(value)) => self.entry_timeout = Some(parse_seconds(value)?),
+                ("attr_timeout", Some(value)) => self.attr_timeout = Some(parse_seconds(value)?),
+                ("negative_timeout", Some(value)) => self.negative_timeout = Some(parse_seconds(value)?),
+                ("keep_cache", None) | ("kernel_cache", None) => self.keep_cache = true,
+                ("direct_io", None) => self.direct_io = true,
+                (key, None) if KERNEL_OPTS.contains(&key) => self.kernel_opts.push(key.to_string()),
+                (key, None) if IGNORED_OPTS.contains(&key) => {}
+                _ => return Err(format!("unknown mount option '{}'", opt)),
+            }
+        }
+        Ok(())
+    }
+
+    /* Returns the file-system configuration these options ask for */
+    pub fn config(&self) -> Config {
+        let defaults = Config::default();
+        Config {
+            root_mode: self.mode.unwrap_or(defaults.root_mode),
+            root_uid: self.uid.unwrap_or(defaults.root_uid),
+            root_gid: self.gid.unwrap_or(defaults.root_gid),
+            max_size: self.size,
+            max_inodes: self.nr_inodes,
+            max_file_size: self.max_file_size,
+            read_only: self.read_only,
+            read_only_on_panic: self.read_only_on_panic,
+            entry_ttl: self.entry_timeout.unwrap_or(defaults.entry_ttl),
+            attr_ttl: self.attr_timeout.unwrap_or(defaults.attr_ttl),
+            negative_ttl: self.negative_timeout.filter(|ttl| !ttl.is_zero()),
+            keep_cache: self.keep_cache,
+            direct_io: self.direct_io,
+        }
+    }
+
+    /* Builds the argument list for fuse::mount. 'ro' is left out on purpose: the file-system enforces it
+     * itself, a read-only kernel mount could never be switched back to read-write at runtime */
+    pub fn fuse_args(&self) -> Vec<OsString> {
+        let fsname = self.fsname.as_deref().unwrap_or("ramfs");
+        let mut opts = vec![format!("fsname={}", fsname), "subtype=ramfs".to_string()];
+        if self.allow_other {
+            opts.push("allow_other".to_string());
+        }
+        opts.extend(self.kernel_opts.iter().cloned());
+        vec![OsString::from("-o"), OsString::from(opts.join(","))]
+    }
+}
+
+/* Parses the command line, including the program name in the first position.
+ * When run as 'mount.ramfs' the conventions of mount(8) helpers are followed instead */
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let program = args.next().unwrap_or_default();
+    let as_helper = Path::new(&program).file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with("mount."));
+
+    let mut opts = Options {
+        mountpoint: PathBuf::new(),
+        fsname: None,
+        size: None,
+        nr_inodes: None,
+        max_file_size: None,
+        mode: None,
+        uid: None,
+        gid: None,
+        allow_other: false,
+        read_only: false,
+        read_only_on_panic: false,
+        entry_timeout: None,
+        attr_timeout: None,
+        negative_timeout: None,
+        keep_cache: false,
+        direct_io: false,
+        foreground: !as_helper, // mount(8) waits for its helper, so it has to detach
+        log_level: None,
+        pidfile: None,
+        control_socket: None,
+        metrics_addr: None,
+        audit_log: None,
+        audit_log_size: 16 << 20,
+        ready_fd: None,
+        kernel_opts: Vec::new(),
+    };
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-f" | "--foreground" => opts.foreground = true,
+            "--daemon" => opts.foreground = false,
+            "-o" => {
+                let list = args.next().ok_or("-o needs an argument")?;
+                opts.apply_mount_opts(&list)?;
+            }
+            "--log-level" => opts.log_level = Some(args.next().ok_or("--log-level needs an argument")?),
+            "--pidfile" => opts.pidfile = Some(PathBuf::from(args.next().ok_or("--pidfile needs an argument")?)),
+            "--control" => opts.control_socket = Some(PathBuf::from(args.next().ok_or("--control needs an argument")?)),
+            "--metrics" => opts.metrics_addr = Some(args.next().ok_or("--metrics needs an argument")?),
+            "--audit-log" => opts.audit_log = Some(PathBuf::from(args.next().ok_or("--audit-log needs an argument")?)),
+            "--audit-log-size" => {
+                let size = args.next().ok_or("--audit-log-size needs an argument")?;
+                opts.audit_log_size = parse_scaled(&size, true)?;
+            }
+            "--ready-fd" => {
+                let fd = args.next().ok_or("--ready-fd needs an argument")?;
+                opts.ready_fd = Some(fd.parse().map_err(|_| format!("invalid file descriptor '{}'", fd))?);
+            }
+            // mount(8) passes these to every helper, none of them matter here
+            "-s" | "-n" | "-v" if as_helper => {}
+            "-t" if as_helper => {
+                args.next();
+            }
+            _ if arg.starts_with("-o") => opts.apply_mount_opts(&arg[2..])?,
+            _ if arg.starts_with("--log-level=") => opts.log_level = Some(arg["--log-level=".len()..].to_string()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    match positional.len() {
+        1 => {}
+        // The first of two arguments is the source, which is what /proc/mounts shows unless fsname= says otherwise
+        2 => {
+            let source = positional.remove(0);
+            opts.fsname = opts.fsname.or(Some(source));
+        }
+        0 => return Err("missing mountpoint".to_string()),
+        _ => return Err("too many arguments".to_string()),
+    }
+    opts.mountpoint = PathBuf::from(positional.remove(0));
+    Ok(Command::Mount(Box::new(opts)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(args: &[&str]) -> Result<Options, String> {
+        match parse(args.iter().map(|arg| arg.to_string()))? {
+            Command::Mount(opts) => Ok(*opts),
+            Command::Help => Err("help".to_string()),
+        }
+    }
+
+    #[test]
+    fn mount_helper() {
+        // mount(8) runs helpers as 'mount.ramfs source mountpoint -o options', with a few flags of its own
+        let opts = mount(&["/sbin/mount.ramfs", "scratch", "/mnt", "-n", "-o", "rw,size=1m,mode=1777,nosuid,relatime,defaults,nofail"]).unwrap();
+        assert_eq!(opts.mountpoint, PathBuf::from("/mnt"));
+        assert_eq!(opts.fsname.as_deref(), Some("scratch"));
+        assert_eq!(opts.size, Some(1 << 20));
+        assert_eq!(opts.mode, Some(0o1777));
+        assert_eq!(opts.kernel_opts, ["nosuid", "relatime"]);
+        assert!(!opts.foreground);
+        assert_eq!(opts.fuse_args()[1], "fsname=scratch,subtype=ramfs,nosuid,relatime");
+        // fsname= wins over the source
+        let opts = mount(&["mount.ramfs", "scratch", "/mnt", "-o", "fsname=other"]).unwrap();
+        assert_eq!(opts.fsname.as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn sizes() {
+        let opts = mount(&["ramfs", "-o", "size=50%,nr_inodes=2k,max_file_size=3g", "/mnt"]).unwrap();
+        assert_eq!(opts.size, Some(physical_memory() / 100 * 50));
+        assert_eq!(opts.nr_inodes, Some(2048));
+        assert_eq!(opts.max_file_size, Some(3 << 30));
+        assert!(opts.foreground);
+        assert_eq!(opts.fsname, None);
+        assert!(mount(&["ramfs", "-o", "nr_inodes=1t", "/mnt"]).is_err()); // 't' and '%' are for sizes only
+        assert!(mount(&["ramfs", "-o", "size=x%", "/mnt"]).is_err());
+    }
+
+    #[test]
+    fn unknown_options() {
+        assert_eq!(mount(&["ramfs", "-o", "bogus", "/mnt"]), Err("unknown mount option 'bogus'".to_string()));
+        assert_eq!(mount(&["ramfs", "-o", "size", "/mnt"]), Err("unknown mount option 'size'".to_string()));
+        assert_eq!(mount(&["ramfs", "-o", "nosuid=1", "/mnt"]), Err("unknown mount option 'nosuid=1'".to_string()));
+        assert_eq!(mount(&["ramfs", "-o", "errors=panic", "/mnt"]), Err("unknown mount option 'errors=panic'".to_string()));
+        assert_eq!(mount(&["ramfs", "-o", "mode=8", "/mnt"]), Err("invalid mode '8'".to_string()));
+        // Flags only mount(8) understands are not allowed without it
+        assert!(mount(&["ramfs", "-n", "/mnt"]).is_err());
+        // Options mount(8) keeps for itself are accepted and dropped
+        let opts = mount(&["ramfs", "-odefaults,auto,_netdev", "/mnt"]).unwrap();
+        assert!(opts.kernel_opts.is_empty());
+    }
+}
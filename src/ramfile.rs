@@ -0,0 +1,210 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::vec;
+use libc::{c_int, EAGAIN, EBADF, EINVAL, EISDIR, F_RDLCK, F_UNLCK, F_WRLCK, O_EXCL, O_RDWR};
+use fuse::{FileAttr, FileType};
+use super::{DirEntry, RamFS, SetAttr};
+use super::lock::{Lock, Owner};
+
+/* An open file in a RamFS, read and written straight from memory without going through the kernel.
+ * It works like std::fs::File, so code written against std::io can run on RamFS unchanged */
+pub struct RamFile {
+    fs: RamFS,
+    ino: u64,
+    fh: u64, // the handle its locks belong to
+    pos: u64,
+}
+
+impl RamFile {
+    fn new(fs: &RamFS, ino: u64) -> RamFile {
+        let mut inner = fs.lock();
+        let fh = inner.handles.open(O_RDWR as u32);
+        let _ = inner.introspect_open(ino, fh); // the inode was just looked up, it can only be there
+        drop(inner);
+        RamFile { fs: fs.clone(), ino, fh, pos: 0 }
+    }
+
+    /* Opens an existing file for reading and writing, starting at the beginning */
+    pub fn open<P: AsRef<Path>>(fs: &RamFS, path: P) -> io::Result<RamFile> {
+        let attr = fs.stat(path)?;
+        if attr.kind == FileType::Directory {
+            return Err(io::Error::from_raw_os_error(EISDIR));
+        }
+        Ok(RamFile::new(fs, attr.ino))
+    }
+
+    /* Opens a file for writing, creating it if needed and truncating it if it already exists */
+    pub fn create<P: AsRef<Path>>(fs: &RamFS, path: P) -> io::Result<RamFile> {
+        let ino = fs.create(path)?.ino;
+        let file = RamFile::new(fs, ino);
+        file.set_len(0)?;
+        Ok(file)
+    }
+
+    /* Creates a new file for writing, failing with EEXIST if the path is taken, like File::create_new */
+    pub fn create_new<P: AsRef<Path>>(fs: &RamFS, path: P) -> io::Result<RamFile> {
+        let ino = fs.create_with_flags(path.as_ref(), O_EXCL as u32)?.ino;
+        Ok(RamFile::new(fs, ino))
+    }
+
+    /* Returns the current attributes of the file */
+    pub fn metadata(&self) -> io::Result<FileAttr> {
+        self.fs.lock().getattr(self.ino).map_err(io::Error::from_raw_os_error)
+    }
+
+    /* Truncates the file to 'size' bytes, the cursor is left where it is */
+    pub fn set_len(&self, size: u64) -> io::Result<()> {
+        let changes = SetAttr { size: Some(size), ..SetAttr::default() };
+        self.fs.change("setattr", |fs| fs.setattr(self.ino, changes).map(|_| ()))
+    }
+
+    /* Makes sure the 'len' bytes from 'offset' are part of the file, like fallocate(2): the file grows with
+     * zeros to cover them if it has to, and never shrinks */
+    pub fn allocate(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.fs.change("fallocate", |fs| fs.fallocate(self.ino, offset, len))
+    }
+
+    /* Takes an exclusive flock(2) lock on the whole file, waiting for other holders to let go. Like
+     * File::lock, the lock belongs to this RamFile and goes away when it is dropped */
+    pub fn lock(&self) -> io::Result<()> {
+        self.set_lock(Owner::Flock(self.fh), Lock::whole(F_WRLCK), true)
+    }
+
+    /* Takes a shared flock(2) lock on the whole file, waiting for an exclusive holder to let go */
+    pub fn lock_shared(&self) -> io::Result<()> {
+        self.set_lock(Owner::Flock(self.fh), Lock::whole(F_RDLCK), true)
+    }
+
+    /* Same as 'lock', failing with EWOULDBLOCK instead of waiting */
+    pub fn try_lock(&self) -> io::Result<()> {
+        self.set_lock(Owner::Flock(self.fh), Lock::whole(F_WRLCK), false)
+    }
+
+    /* Same as 'lock_shared', failing with EWOULDBLOCK instead of waiting */
+    pub fn try_lock_shared(&self) -> io::Result<()> {
+        self.set_lock(Owner::Flock(self.fh), Lock::whole(F_RDLCK), false)
+    }
+
+    /* Drops the flock(2) lock taken with any of the above */
+    pub fn unlock(&self) -> io::Result<()> {
+        self.set_lock(Owner::Flock(self.fh), Lock::whole(F_UNLCK), false)
+    }
+
+    /* Takes a record lock on 'len' bytes from 'start', like F_OFD_SETLKW. A 'len' of 0 runs to the end of
+     * the file however far it grows. Record locks conflict with those of other RamFiles, not with flock locks */
+    pub fn lock_range(&self, start: u64, len: u64, exclusive: bool) -> io::Result<()> {
+        self.set_lock(Owner::Handle(self.fh), range(start, len, if exclusive { F_WRLCK } else { F_RDLCK })?, true)
+    }
+
+    /* Same as 'lock_range', failing with EWOULDBLOCK instead of waiting, like F_OFD_SETLK */
+    pub fn try_lock_range(&self, start: u64, len: u64, exclusive: bool) -> io::Result<()> {
+        self.set_lock(Owner::Handle(self.fh), range(start, len, if exclusive { F_WRLCK } else { F_RDLCK })?, false)
+    }
+
+    /* Drops the record locks of this RamFile on 'len' bytes from 'start', splitting any that reach past them */
+    pub fn unlock_range(&self, start: u64, len: u64) -> io::Result<()> {
+        self.set_lock(Owner::Handle(self.fh), range(start, len, F_UNLCK)?, false)
+    }
+
+    fn set_lock(&self, owner: Owner, lock: Lock, wait: bool) -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let parked = self.fs.change("setlk", |fs| match fs.setlk(self.ino, owner, lock) {
+            Err(EAGAIN) if wait => fs.locks.wait(self.ino, owner, lock, || Box::new(move |result| {
+                let _ = sender.send(result);
+            })).map(|()| true),
+            result => result.map(|()| false),
+        })?;
+        if !parked {
+            return Ok(());
+        }
+        // Whoever frees up the range grants the lock, with the file-system unlocked in the meantime
+        receiver.recv().unwrap_or(Err(EBADF)).map_err(io::Error::from_raw_os_error)
+    }
+}
+
+/* The record lock on 'len' bytes from 'start', with 0 standing for the rest of the file */
+fn range(start: u64, len: u64, typ: c_int) -> io::Result<Lock> {
+    let end = match len {
+        0 => u64::MAX,
+        len => start.checked_add(len - 1).ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?,
+    };
+    Ok(Lock { start, end, typ })
+}
+
+// Closing the file drops its locks and wakes whoever waits on them
+impl Drop for RamFile {
+    fn drop(&mut self) {
+        let _ = self.fs.change("release", |fs| {
+            fs.release(self.ino, self.fh);
+            Ok(())
+        });
+    }
+}
+
+impl Read for RamFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let fs = self.fs.lock();
+        let size = buf.len().min(u32::MAX as usize) as u32;
+        let data = fs.read(self.ino, self.fh, self.pos as i64, size).map_err(io::Error::from_raw_os_error)?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+impl Write for RamFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = buf.len().min(u32::MAX as usize);
+        let written = self.fs.change("write", |fs| fs.write(self.ino, self.pos as i64, &buf[..size]))?;
+        self.pos += u64::from(written);
+        Ok(written as usize)
+    }
+
+    // Every write already lands in the tree, there is nothing buffered here
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for RamFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(delta) => (self.metadata()?.size, delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        match base.checked_add_signed(delta) {
+            Some(offset) => {
+                self.pos = offset;
+                Ok(offset)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
+/* Iterator over the entries of a directory, like std::fs::ReadDir. The listing is taken when the
+ * iterator is created, so changes made while iterating do not show up in it */
+pub struct ReadDir {
+    entries: vec::IntoIter<DirEntry>,
+}
+
+impl Iterator for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<io::Result<DirEntry>> {
+        self.entries.next().map(Ok)
+    }
+}
+
+impl RamFS {
+    /* Returns an iterator over the entries of a directory, the counterpart of std::fs::read_dir */
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<ReadDir> {
+        Ok(ReadDir { entries: self.readdir(path)?.into_iter() })
+    }
+}
+
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use libc::{c_int, EEXIST, ENOENT, ENOTDIR, EISDIR};
+use fuse::{FileAttr, FileType};
+use super::{dir, Inner, Inode, RamFS, Tree};
+
+// Name of the hidden directory at the root of the mount that holds every snapshot
+pub const SNAPSHOT_DIR: &str = ".snapshots";
+
+// Snapshot inode numbers carry the snapshot id in their upper bits, so they can never collide with
+// the live tree. The live tree would need 2^40 inodes before it reached that range.
+const ID_SHIFT: u32 = 40;
+const INO_MASK: u64 = (1 << ID_SHIFT) - 1;
+pub const SNAPSHOT_DIR_INO: u64 = INO_MASK;
+
+/* A frozen copy of the tree. It shares every unchanged file and inode with the live tree */
+struct Snapshot {
+    name: String,
+    tree: Tree,
+}
+
+#[derive(Default)]
+pub struct Snapshots {
+    by_id: BTreeMap<u64, Snapshot>,
+    ids: BTreeMap<String, u64>,
+    last_id: u64,
+}
+
+/* Tells if an inode lives in the snapshot area rather than the live tree */
+pub fn owns(ino: u64) -> bool {
+    ino == SNAPSHOT_DIR_INO || ino >> ID_SHIFT != 0
+}
+
+/* Snapshot names become directory names, so they follow the same rules, with '.' and '..' turned away
+ * as invalid rather than taken */
+fn check_name(name: &str) -> Result<(), c_int> {
+    super::check_old_name(OsStr::new(name))
+}
+
+impl Snapshots {
+    /* Returns how many snapshots there are */
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    /* Returns the name and tree of every snapshot, in the order they were taken */
+    pub(crate) fn trees(&self) -> impl Iterator<Item = (&str, &Tree)> {
+        self.by_id.values().map(|snap| (snap.name.as_str(), &snap.tree))
+    }
+
+    /* Finds the snapshot an inode belongs to and the inode number inside that snapshot's tree */
+    fn resolve(&self, ino: u64) -> Option<(u64, &Snapshot, u64)> {
+        let id = ino >> ID_SHIFT;
+        self.by_id.get(&id).map(|snap| (id, snap, ino & INO_MASK))
+    }
+}
+
+impl RamFS {
+    /* Takes a named, read-only snapshot of the live tree. This only copies a few pointers,
+     * the data is shared until the live tree changes it */
+    pub fn snapshot(&self, name: &str) -> io::Result<()> {
+        self.change("snapshot", |fs| fs.snapshot(name))
+    }
+
+    /* Rolls the live tree back to a snapshot. The snapshot itself is kept so it can be restored again */
+    pub fn restore_snapshot(&self, name: &str) -> io::Result<()> {
+        self.change("restore_snapshot", |fs| fs.restore_snapshot(name))
+    }
+
+    /* Drops a snapshot, releasing every piece of data only it was holding on to */
+    pub fn remove_snapshot(&self, name: &str) -> io::Result<()> {
+        self.change("remove_snapshot", |fs| fs.remove_snapshot(name))
+    }
+
+    /* Returns the names of all the snapshots in the order they were taken */
+    pub fn snapshot_names(&self) -> Vec<String> {
+        self.lock().snapshots.by_id.values().map(|snap| snap.name.clone()).collect()
+    }
+}
+
+impl Inner {
+    pub(crate) fn snapshot(&mut self, name: &str) -> Result<(), c_int> {
+        check_name(name)?;
+        if self.snapshots.ids.contains_key(name) {
+            return Err(EEXIST);
+        }
+        self.snapshots.last_id += 1;
+        let id = self.snapshots.last_id;
+        let snap = Snapshot { name: name.to_string(), tree: self.tree.clone() };
+        self.snapshots.by_id.insert(id, snap);
+        self.snapshots.ids.insert(name.to_string(), id);
+        Ok(())
+    }
+
+    pub(crate) fn restore_snapshot(&mut self, name: &str) -> Result<(), c_int> {
+        self.check_writable()?; // Restoring rewrites the whole live tree
+        let id = *self.snapshots.ids.get(name).ok_or(ENOENT)?;
+        let mut tree = self.snapshots.by_id[&id].tree.clone();
+        // Never hand out an inode number again, the kernel may still have the newer ones cached
+        tree.next_inode = tree.next_inode.max(self.tree.next_inode);
+        self.tree = tree;
+        Ok(())
+    }
+
+    pub(crate) fn remove_snapshot(&mut self, name: &str) -> Result<(), c_int> {
+        let id = self.snapshots.ids.remove(name).ok_or(ENOENT)?;
+        self.snapshots.by_id.remove(&id);
+        Ok(())
+    }
+
+    /* Attributes of the '.snapshots' directory itself, borrowed from the live root */
+    fn snapshot_dir_attr(&self) -> FileAttr {
+        let mut attr = self.tree.inodes[&1].attr;
+        attr.ino = SNAPSHOT_DIR_INO;
+        attr.perm = 0o755;
+        attr.nlink = 2 + self.snapshots.len() as u32; // every snapshot's root has a '..' leading here
+        attr.size = dir::size(self.snapshots.len());
+        attr
+    }
+
+    /* Returns attributes from a snapshot, with the inode renumbered and write permissions dropped */
+    pub(crate) fn snapshot_getattr(&self, ino: u64) -> Result<FileAttr, c_int> {
+        if ino == SNAPSHOT_DIR_INO {
+            return Ok(self.snapshot_dir_attr());
+        }
+        let mut attr = self.snapshot_inode(ino)?.attr;
+        attr.ino = ino;
+        attr.perm &= !0o222;
+        Ok(attr)
+    }
+
+    /* Looks up 'name' under a snapshot directory, or '.snapshots' itself under the live root */
+    pub(crate) fn snapshot_lookup(&self, parent: u64, name: &OsStr) -> Result<FileAttr, c_int> {
+        if parent == 1 {
+            return Ok(self.snapshot_dir_attr());
+        }
+        if parent == SNAPSHOT_DIR_INO {
+            let id = self.snapshots.ids.get(name.to_str().ok_or(ENOENT)?).ok_or(ENOENT)?;
+            return self.snapshot_getattr((id << ID_SHIFT) | 1);
+        }
+        let (id, snap, inner) = self.snapshots.resolve(parent).ok_or(ENOENT)?;
+        let entries = snap.tree.get(inner).ok_or(ENOENT)?.entries().ok_or(ENOTDIR)?;
+        let child = entries.get(name).ok_or(ENOENT)?;
+        self.snapshot_getattr((id << ID_SHIFT) | child)
+    }
+
+    /* Lists either the snapshots themselves or a directory inside one of them */
+    pub(crate) fn snapshot_readdir(&self, ino: u64, offset: i64, add: &mut dyn FnMut(u64, i64, FileType, &OsStr) -> bool) -> Result<(), c_int> {
+        if ino == SNAPSHOT_DIR_INO {
+            // Ids are never reused, so they make stable cookies past the two of '.' and '..'
+            let first = (offset - 2).max(0) as u64 + 1;
+            let snapshots = self.snapshots.by_id.range(first..)
+                .map(|(id, snap)| ((id << ID_SHIFT) | 1, *id as i64 + 2, FileType::Directory, OsStr::new(&snap.name)));
+            dir::list(ino, 1, offset, snapshots, add);
+            return Ok(());
+        }
+        let (id, snap, inner) = self.snapshots.resolve(ino).ok_or(ENOENT)?;
+        let inode = snap.tree.get(inner).ok_or(ENOENT)?;
+        // The parent of a snapshot's root is '.snapshots'
+        let parent = if inner == 1 { SNAPSHOT_DIR_INO } else { (id << ID_SHIFT) | inode.root };
+        let children = inode.entries().ok_or(ENOTDIR)?.after(offset)
+            .filter_map(|(cookie, name, child)| snap.tree.get(child).map(|child_inode| ((id << ID_SHIFT) | child, cookie, child_inode.payload.kind(), name)));
+        dir::list(ino, parent, offset, children, add);
+        Ok(())
+    }
+
+    /* Returns the paths of a snapshot inode, as seen under '.snapshots' */
+    pub(crate) fn snapshot_paths(&self, ino: u64) -> Vec<PathBuf> {
+        let (_, snap, inner) = match self.snapshots.resolve(ino) {
+            Some(resolved) => resolved,
+            None => return Vec::new(),
+        };
+        let root = Path::new("/").join(SNAPSHOT_DIR).join(&snap.name);
+        snap.tree.paths(inner).into_iter()
+            .map(|path| match path.strip_prefix("/") {
+                Ok(rest) if !rest.as_os_str().is_empty() => root.join(rest),
+                _ => root.clone(),
+            })
+            .collect()
+    }
+
+    /* Finds an inode inside one of the snapshots */
+    pub(crate) fn snapshot_inode(&self, ino: u64) -> Result<&Inode, c_int> {
+        let (_, snap, inner) = self.snapshots.resolve(ino).ok_or(ENOENT)?;
+        snap.tree.get(inner).ok_or(ENOENT)
+    }
+
+    /* Hands back the contents of a file in a snapshot */
+    pub(crate) fn snapshot_read(&self, ino: u64) -> Result<&[u8], c_int> {
+        if ino == SNAPSHOT_DIR_INO {
+            return Err(EISDIR);
+        }
+        let file = self.snapshot_inode(ino)?.file_or_err()?;
+        Ok(&file.data[..])
+    }
+}
@@ -0,0 +1,85 @@
+use std::ffi::OsString;
+use ramfs::{DirEntry, FileType, RamFS};
+
+mod common;
+use common::errno;
+
+fn names(fs: &RamFS, path: &str) -> Vec<OsString> {
+    fs.readdir(path).unwrap().into_iter().map(|entry| entry.name).collect()
+}
+
+#[test]
+fn create_read_write() {
+    let fs = RamFS::new();
+    let attr = fs.create("/file").unwrap();
+    assert_eq!((attr.kind, attr.size), (FileType::RegularFile, 0));
+    fs.write("/file", b"hello").unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"hello");
+    // Creating it again hands back the file as it is
+    assert_eq!(fs.create("/file").unwrap().ino, attr.ino);
+    assert_eq!(fs.read("/file").unwrap(), b"hello");
+    // Writing replaces the whole contents, creating the file if needed
+    fs.write("/file", b"hi").unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"hi");
+    fs.write("/new", b"").unwrap();
+    assert_eq!(fs.stat("/new").unwrap().size, 0);
+    assert_eq!(fs.stat("/file").unwrap().size, 2);
+}
+
+#[test]
+fn relative_paths_and_dots() {
+    let fs = RamFS::new();
+    fs.mkdir("/a").unwrap();
+    fs.write("a/file", b"data").unwrap();
+    assert_eq!(fs.read("/a/./file").unwrap(), b"data");
+    assert_eq!(fs.read("/../a/../a/file").unwrap(), b"data");
+    assert_eq!(fs.stat("/").unwrap().ino, 1);
+}
+
+#[test]
+fn readdir() {
+    let fs = RamFS::new();
+    fs.mkdir("/dir").unwrap();
+    let sub = fs.mkdir("/dir/sub").unwrap();
+    let file = fs.create("/dir/file").unwrap();
+    assert_eq!(fs.readdir("/dir").unwrap(), vec![
+        DirEntry { name: OsString::from("sub"), ino: sub.ino, kind: FileType::Directory },
+        DirEntry { name: OsString::from("file"), ino: file.ino, kind: FileType::RegularFile },
+    ]);
+    assert_eq!(fs.read_dir("/dir").unwrap().map(|entry| entry.unwrap().name).collect::<Vec<_>>(), names(&fs, "/dir"));
+    assert!(fs.readdir("/dir/sub").unwrap().is_empty());
+    assert_eq!(errno(fs.readdir("/dir/file")), libc::ENOTDIR);
+}
+
+#[test]
+fn rename() {
+    let fs = RamFS::new();
+    fs.mkdir("/a").unwrap();
+    fs.mkdir("/b").unwrap();
+    fs.write("/a/file", b"data").unwrap();
+    fs.rename("/a/file", "/b/moved").unwrap();
+    assert_eq!(fs.read("/b/moved").unwrap(), b"data");
+    assert_eq!(errno(fs.stat("/a/file")), libc::ENOENT);
+    // The target is replaced
+    fs.write("/b/other", b"other").unwrap();
+    fs.rename("/b/other", "/b/moved").unwrap();
+    assert_eq!(fs.read("/b/moved").unwrap(), b"other");
+    assert_eq!(names(&fs, "/b"), ["moved"]);
+    // Directories take their contents along
+    fs.rename("/b", "/a/b").unwrap();
+    assert_eq!(fs.read("/a/b/moved").unwrap(), b"other");
+    assert_eq!(names(&fs, "/"), ["a"]);
+}
+
+#[test]
+fn remove() {
+    let fs = RamFS::new();
+    fs.mkdir("/dir").unwrap();
+    fs.write("/dir/file", b"data").unwrap();
+    assert_eq!(errno(fs.remove("/dir")), libc::ENOTEMPTY);
+    fs.remove("/dir/file").unwrap();
+    fs.remove("/dir").unwrap();
+    assert!(fs.readdir("/").unwrap().is_empty());
+    assert_eq!(errno(fs.remove("/dir")), libc::ENOENT);
+    assert_eq!(fs.stats().bytes, 0);
+}
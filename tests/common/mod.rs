@@ -0,0 +1,6 @@
+use std::io;
+
+/* Returns the errno a call failed with, failing the test if it worked */
+pub fn errno<T: std::fmt::Debug>(result: io::Result<T>) -> i32 {
+    result.expect_err("expected an error").raw_os_error().expect("expected an errno")
+}
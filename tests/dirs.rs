@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+use ramfs::{Config, RamFS};
+
+mod common;
+use common::errno;
+
+fn nlink(fs: &RamFS, path: &str) -> u32 {
+    fs.stat(path).unwrap().nlink
+}
+
+#[test]
+fn nlink_counts_subdirectories() {
+    let fs = RamFS::new();
+    assert_eq!(nlink(&fs, "/"), 2);
+    fs.mkdir("/a").unwrap();
+    fs.mkdir("/b").unwrap();
+    fs.write("/file", b"").unwrap();
+    assert_eq!(nlink(&fs, "/"), 4);
+    assert_eq!(nlink(&fs, "/a"), 2);
+    assert_eq!(nlink(&fs, "/file"), 1);
+
+    fs.rename("/b", "/a/b").unwrap();
+    assert_eq!(nlink(&fs, "/"), 3);
+    assert_eq!(nlink(&fs, "/a"), 3);
+
+    fs.mkdir("/c").unwrap();
+    fs.rename("/c", "/a/b").unwrap();
+    assert_eq!(nlink(&fs, "/"), 3);
+    assert_eq!(nlink(&fs, "/a"), 3);
+
+    fs.remove_dir("/a/b").unwrap();
+    assert_eq!(nlink(&fs, "/a"), 2);
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn size_follows_entries() {
+    let fs = RamFS::new();
+    let empty = fs.mkdir("/dir").unwrap().size;
+    assert!(empty > 0);
+    fs.write("/dir/one", b"").unwrap();
+    let one = fs.stat("/dir").unwrap().size;
+    assert!(one > empty);
+    fs.write("/dir/two", b"").unwrap();
+    assert_eq!(fs.stat("/dir").unwrap().size - one, one - empty);
+
+    fs.rename("/dir/two", "/two").unwrap();
+    assert_eq!(fs.stat("/dir").unwrap().size, one);
+    fs.remove_file("/dir/one").unwrap();
+    assert_eq!(fs.stat("/dir").unwrap().size, empty);
+}
+
+#[test]
+fn new_inodes_belong_to_the_root_owner() {
+    let fs = RamFS::with_config(Config { root_uid: 1000, root_gid: 100, ..Config::default() });
+    let dir = fs.mkdir("/dir").unwrap();
+    let file = fs.create("/dir/file").unwrap();
+    assert_eq!((dir.perm, dir.uid, dir.gid), (0o755, 1000, 100));
+    assert_eq!((file.perm, file.uid, file.gid), (0o644, 1000, 100));
+}
+
+#[test]
+fn hard_links() {
+    let fs = RamFS::new();
+    fs.mkdir("/a").unwrap();
+    fs.write("/a/file", b"data").unwrap();
+    let ino = fs.hard_link("/a/file", "/link").unwrap().ino;
+    assert_eq!(nlink(&fs, "/link"), 2);
+    assert_eq!(fs.stat("/a/file").unwrap().ino, ino);
+    assert_eq!(fs.paths(ino), vec![PathBuf::from("/a/file"), PathBuf::from("/link")]);
+    assert_eq!(errno(fs.hard_link("/a", "/dir")), libc::EPERM);
+    assert_eq!(errno(fs.hard_link("/a/file", "/link")), libc::EEXIST);
+
+    // The data stays until the last link goes, whichever link the file was created by
+    fs.remove_file("/a/file").unwrap();
+    assert_eq!(fs.paths(ino), vec![PathBuf::from("/link")]);
+    assert_eq!(fs.read("/link").unwrap(), b"data");
+    fs.hard_link("/link", "/a/again").unwrap();
+    fs.write("/other", b"").unwrap();
+    fs.rename("/other", "/link").unwrap();
+    assert_eq!(fs.paths(ino), vec![PathBuf::from("/a/again")]);
+    assert_eq!(nlink(&fs, "/a/again"), 1);
+    fs.remove_file("/a/again").unwrap();
+    assert!(fs.paths(ino).is_empty());
+    assert!(fs.fsck().is_empty());
+}
@@ -0,0 +1,119 @@
+use ramfs::RamFS;
+
+mod common;
+use common::errno;
+
+/* A directory '/dir' holding a file, an empty directory '/empty' and a file '/file' */
+fn tree() -> RamFS {
+    let fs = RamFS::new();
+    fs.mkdir("/dir").unwrap();
+    fs.write("/dir/inner", b"inner").unwrap();
+    fs.mkdir("/empty").unwrap();
+    fs.write("/file", b"file").unwrap();
+    fs
+}
+
+#[test]
+fn unlink_directory() {
+    let fs = tree();
+    assert_eq!(errno(fs.remove_file("/empty")), libc::EISDIR);
+    assert_eq!(errno(fs.remove_file("/dir")), libc::EISDIR);
+    assert!(fs.stat("/empty").is_ok());
+    assert_eq!(fs.read("/dir/inner").unwrap(), b"inner");
+}
+
+#[test]
+fn rmdir_file() {
+    let fs = tree();
+    assert_eq!(errno(fs.remove_dir("/file")), libc::ENOTDIR);
+    assert_eq!(fs.read("/file").unwrap(), b"file");
+}
+
+#[test]
+fn rmdir_not_empty() {
+    let fs = tree();
+    assert_eq!(errno(fs.remove_dir("/dir")), libc::ENOTEMPTY);
+    fs.remove_file("/dir/inner").unwrap();
+    fs.remove_dir("/dir").unwrap();
+}
+
+#[test]
+fn missing_entries() {
+    let fs = tree();
+    assert_eq!(errno(fs.remove_file("/missing")), libc::ENOENT);
+    assert_eq!(errno(fs.remove_dir("/missing")), libc::ENOENT);
+    assert_eq!(errno(fs.read("/missing")), libc::ENOENT);
+    assert_eq!(errno(fs.mkdir("/missing/dir")), libc::ENOENT);
+    assert_eq!(errno(fs.rename("/missing", "/other")), libc::ENOENT);
+}
+
+#[test]
+fn file_as_parent() {
+    let fs = tree();
+    assert_eq!(errno(fs.stat("/file/x")), libc::ENOTDIR);
+    assert_eq!(errno(fs.create("/file/x")), libc::ENOTDIR);
+    assert_eq!(errno(fs.mkdir("/file/x")), libc::ENOTDIR);
+    assert_eq!(errno(fs.remove_file("/file/x")), libc::ENOTDIR);
+    assert_eq!(errno(fs.remove_dir("/file/x")), libc::ENOTDIR);
+    assert_eq!(errno(fs.readdir("/file")), libc::ENOTDIR);
+    assert_eq!(errno(fs.rename("/file/x", "/y")), libc::ENOTDIR);
+    assert_eq!(errno(fs.rename("/dir/inner", "/file/x")), libc::ENOTDIR);
+}
+
+#[test]
+fn directory_as_file() {
+    let fs = tree();
+    assert_eq!(errno(fs.read("/dir")), libc::EISDIR);
+    assert_eq!(errno(fs.write("/dir", b"data")), libc::EISDIR);
+    assert_eq!(errno(fs.create("/dir")), libc::EISDIR);
+    assert_eq!(fs.read("/dir/inner").unwrap(), b"inner");
+}
+
+#[test]
+fn rename_across_kinds() {
+    let fs = tree();
+    assert_eq!(errno(fs.rename("/empty", "/file")), libc::ENOTDIR);
+    assert_eq!(errno(fs.rename("/file", "/empty")), libc::EISDIR);
+    assert_eq!(errno(fs.rename("/empty", "/dir")), libc::ENOTEMPTY);
+    fs.mkdir("/other").unwrap();
+    fs.rename("/dir", "/other").unwrap();
+    assert_eq!(fs.read("/other/inner").unwrap(), b"inner");
+    fs.write("/other/inner2", b"2").unwrap();
+    fs.rename("/other/inner2", "/file").unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"2");
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn rename_into_itself() {
+    let fs = tree();
+    fs.mkdir("/dir/sub").unwrap();
+    assert_eq!(errno(fs.rename("/dir", "/dir/sub/moved")), libc::EINVAL);
+    assert_eq!(errno(fs.rename("/dir", "/dir/moved")), libc::EINVAL);
+    fs.rename("/dir/sub", "/sub").unwrap();
+    fs.rename("/file", "/file").unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"file");
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn hidden_directories() {
+    let fs = tree();
+    assert_eq!(errno(fs.read("/.ramfs")), libc::EISDIR);
+    assert_eq!(errno(fs.readdir("/.ramfs/stats")), libc::ENOTDIR);
+    assert_eq!(errno(fs.stat("/.ramfs/stats/x")), libc::ENOTDIR);
+    fs.snapshot("snap").unwrap();
+    assert_eq!(errno(fs.read("/.snapshots/snap/dir")), libc::EISDIR);
+    assert_eq!(errno(fs.readdir("/.snapshots/snap/file")), libc::ENOTDIR);
+    assert_eq!(errno(fs.stat("/.snapshots/snap/file/x")), libc::ENOTDIR);
+}
+
+#[test]
+fn create_new_existing() {
+    let fs = tree();
+    assert_eq!(errno(ramfs::RamFile::create_new(&fs, "/file").map(|_| ())), libc::EEXIST);
+    assert_eq!(errno(ramfs::RamFile::create_new(&fs, "/dir").map(|_| ())), libc::EEXIST);
+    assert_eq!(fs.read("/file").unwrap(), b"file");
+    ramfs::RamFile::create_new(&fs, "/new").unwrap();
+    assert_eq!(fs.read("/new").unwrap(), b"");
+}
@@ -0,0 +1,80 @@
+use std::io;
+use ramfs::{RamFS, APPEND_ONLY, IMMUTABLE};
+
+mod common;
+use common::errno;
+
+#[test]
+fn immutable_file() {
+    let fs = RamFS::new();
+    fs.write("/file", b"data").unwrap();
+    assert_eq!(fs.set_flags("/file", IMMUTABLE, 0).unwrap().flags, IMMUTABLE);
+    assert_eq!(errno(fs.write("/file", b"new")), libc::EPERM);
+    assert_eq!(errno(fs.remove_file("/file")), libc::EPERM);
+    assert_eq!(errno(fs.rename("/file", "/moved")), libc::EPERM);
+    fs.write("/other", b"other").unwrap();
+    assert_eq!(errno(fs.rename("/other", "/file")), libc::EPERM);
+    assert_eq!(errno(fs.hard_link("/file", "/link")), libc::EPERM);
+    assert_eq!(fs.read("/file").unwrap(), b"data");
+
+    fs.set_flags("/file", 0, 0).unwrap();
+    fs.remove_file("/file").unwrap();
+}
+
+#[test]
+fn immutable_directory() {
+    let fs = RamFS::new();
+    fs.mkdir("/dir").unwrap();
+    fs.write("/dir/file", b"data").unwrap();
+    fs.set_flags("/dir", IMMUTABLE, 0).unwrap();
+    assert_eq!(errno(fs.create("/dir/new")), libc::EPERM);
+    assert_eq!(errno(fs.mkdir("/dir/sub")), libc::EPERM);
+    fs.write("/outside", b"").unwrap();
+    assert_eq!(errno(fs.hard_link("/outside", "/dir/link")), libc::EPERM);
+    assert_eq!(errno(fs.remove_file("/dir/file")), libc::EPERM);
+    assert_eq!(errno(fs.rename("/dir/file", "/file")), libc::EPERM);
+    // The files in it can still be written
+    fs.write("/dir/file", b"changed").unwrap();
+}
+
+#[test]
+fn append_only() {
+    let fs = RamFS::new();
+    fs.mkdir("/logs").unwrap();
+    fs.write("/logs/log", b"one\n").unwrap();
+    fs.set_flags("/logs/log", APPEND_ONLY, 0).unwrap();
+    fs.set_flags("/logs", APPEND_ONLY, 0).unwrap();
+
+    let mut log = ramfs::RamFile::open(&fs, "/logs/log").unwrap();
+    assert_eq!(errno(io::Write::write(&mut log, b"over")), libc::EPERM);
+    io::Seek::seek(&mut log, io::SeekFrom::End(0)).unwrap();
+    io::Write::write_all(&mut log, b"two\n").unwrap();
+    assert_eq!(errno(log.set_len(0)), libc::EPERM);
+    assert_eq!(errno(fs.hard_link("/logs/log", "/log")), libc::EPERM);
+    assert_eq!(fs.read("/logs/log").unwrap(), b"one\ntwo\n");
+
+    // Entries can be added to the directory but never taken out
+    fs.write("/logs/new", b"").unwrap();
+    assert_eq!(errno(fs.remove_file("/logs/new")), libc::EPERM);
+    assert_eq!(errno(fs.rename("/logs/new", "/new")), libc::EPERM);
+}
+
+#[test]
+fn unsupported_flags() {
+    let fs = RamFS::new();
+    fs.write("/file", b"").unwrap();
+    assert_eq!(errno(fs.set_flags("/file", 0x1, 0)), libc::EOPNOTSUPP);
+    assert_eq!(fs.stat("/file").unwrap().flags, 0);
+}
+
+#[test]
+fn only_root_changes_flags() {
+    let fs = RamFS::new();
+    fs.write("/file", b"").unwrap();
+    assert_eq!(errno(fs.set_flags("/file", IMMUTABLE, 1000)), libc::EPERM);
+    fs.set_flags("/file", IMMUTABLE, 0).unwrap();
+    assert_eq!(errno(fs.set_flags("/file", 0, 1000)), libc::EPERM);
+    // Asking for the flags already there changes nothing, which anyone may do
+    fs.set_flags("/file", IMMUTABLE, 1000).unwrap();
+    assert_eq!(fs.stat("/file").unwrap().flags, IMMUTABLE);
+}
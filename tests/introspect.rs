@@ -0,0 +1,71 @@
+use std::io::Read;
+use ramfs::{FileType, RamFile, RamFS};
+
+mod common;
+use common::errno;
+
+fn stat_line(stats: &[u8], key: &str) -> String {
+    let stats = String::from_utf8(stats.to_vec()).unwrap();
+    stats.lines().find_map(|line| line.strip_prefix(key).map(|value| value.trim().to_string())).unwrap()
+}
+
+#[test]
+fn hidden_from_the_root() {
+    let fs = RamFS::new();
+    fs.write("/file", b"data").unwrap();
+    let names: Vec<_> = fs.readdir("/").unwrap().into_iter().map(|entry| entry.name).collect();
+    assert_eq!(names, ["file"]);
+    // It is still there when named
+    assert_eq!(fs.stat("/.ramfs").unwrap().kind, FileType::Directory);
+    let names: Vec<_> = fs.readdir("/.ramfs").unwrap().into_iter().map(|entry| entry.name).collect();
+    assert_eq!(names, ["stats", "inodes", "config"]);
+}
+
+#[test]
+fn live_stats() {
+    let fs = RamFS::new();
+    assert_eq!(stat_line(&fs.read("/.ramfs/stats").unwrap(), "inodes"), "1");
+    fs.mkdir("/dir").unwrap();
+    fs.write("/dir/file", b"hello").unwrap();
+    let stats = fs.read("/.ramfs/stats").unwrap();
+    assert_eq!(stat_line(&stats, "inodes"), "3");
+    assert_eq!(stat_line(&stats, "bytes"), "5");
+    fs.snapshot("snap").unwrap();
+    assert_eq!(stat_line(&fs.read("/.ramfs/stats").unwrap(), "snapshots"), "1");
+    let inodes = String::from_utf8(fs.read("/.ramfs/inodes").unwrap()).unwrap();
+    assert!(inodes.lines().any(|line| line.ends_with(" /dir/file")));
+}
+
+#[test]
+fn rendered_once_per_open() {
+    let fs = RamFS::new();
+    let mut stats = RamFile::open(&fs, "/.ramfs/stats").unwrap();
+    fs.write("/file", b"data").unwrap();
+    // An open file keeps what it had when it was opened, however it is read
+    let mut first = [0; 7];
+    stats.read_exact(&mut first).unwrap();
+    let mut rest = Vec::new();
+    stats.read_to_end(&mut rest).unwrap();
+    assert_eq!(stat_line(&[&first[..], &rest].concat(), "inodes"), "1");
+    let mut stats = RamFile::open(&fs, "/.ramfs/stats").unwrap();
+    let mut contents = Vec::new();
+    stats.read_to_end(&mut contents).unwrap();
+    assert_eq!(stat_line(&contents, "inodes"), "2");
+    // The files are not rendered just to be stat'ed, so like in /proc they have no size
+    assert_eq!(fs.stat("/.ramfs/inodes").unwrap().size, 0);
+}
+
+#[test]
+fn read_only() {
+    let fs = RamFS::new();
+    assert_eq!(errno(fs.write("/.ramfs/stats", b"x")), libc::EROFS);
+    assert_eq!(errno(fs.create("/.ramfs/new")), libc::EROFS);
+    assert_eq!(errno(fs.mkdir("/.ramfs/dir")), libc::EROFS);
+    assert_eq!(errno(fs.remove_file("/.ramfs/stats")), libc::EROFS);
+    assert_eq!(errno(fs.remove_dir("/.ramfs")), libc::EROFS);
+    assert_eq!(errno(fs.rename("/.ramfs/config", "/config")), libc::EROFS);
+    assert_eq!(errno(fs.rename("/.ramfs", "/elsewhere")), libc::EROFS);
+    assert_eq!(errno(RamFile::open(&fs, "/.ramfs/stats").unwrap().set_len(0)), libc::EROFS);
+    assert_eq!(errno(fs.mkdir("/.ramfs")), libc::EEXIST);
+    assert!(fs.fsck().is_empty());
+}
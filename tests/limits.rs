@@ -0,0 +1,72 @@
+use std::io::{Seek, SeekFrom, Write};
+use ramfs::{Config, RamFile, RamFS};
+
+mod common;
+use common::errno;
+
+fn limited(max_file_size: u64) -> RamFS {
+    RamFS::with_config(Config { max_file_size: Some(max_file_size), ..Config::default() })
+}
+
+#[test]
+fn write_past_max_file_size() {
+    let fs = limited(10);
+    fs.write("/fits", b"0123456789").unwrap();
+    assert_eq!(errno(fs.write("/big", b"0123456789a")), libc::EFBIG);
+    // The write that crosses the limit stops at it, the next one fails
+    assert_eq!(fs.read("/big").unwrap(), b"0123456789");
+
+    let mut file = RamFile::open(&fs, "/fits").unwrap();
+    file.seek(SeekFrom::Start(8)).unwrap();
+    assert_eq!(file.write(b"abcd").unwrap(), 2);
+    assert_eq!(errno(file.write(b"cd")), libc::EFBIG);
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn empty_write_past_the_end() {
+    // Writing nothing never grows a file, whether or not there is a limit it would cross
+    for fs in [limited(10), RamFS::new()] {
+        fs.write("/file", b"data").unwrap();
+        let mut file = RamFile::open(&fs, "/file").unwrap();
+        file.seek(SeekFrom::Start(1000)).unwrap();
+        assert_eq!(file.write(b"").unwrap(), 0);
+        assert_eq!(fs.stat("/file").unwrap().size, 4);
+        assert_eq!(fs.stats().bytes, 4);
+    }
+}
+
+#[test]
+fn write_at_the_largest_offset() {
+    let fs = RamFS::new();
+    let mut file = RamFile::create(&fs, "/file").unwrap();
+    file.seek(SeekFrom::Start(u64::MAX - 1)).unwrap();
+    assert_eq!(errno(file.write(b"xyz")), libc::EFBIG);
+    assert_eq!(fs.stat("/file").unwrap().size, 0);
+}
+
+#[test]
+fn truncate_and_allocate_past_max_file_size() {
+    let fs = limited(1 << 20);
+    let file = RamFile::create(&fs, "/file").unwrap();
+    assert_eq!(errno(file.set_len((1 << 20) + 1)), libc::EFBIG);
+    assert_eq!(errno(file.allocate(1 << 20, 1)), libc::EFBIG);
+    file.allocate(0, 1 << 20).unwrap();
+    assert_eq!(fs.stat("/file").unwrap().size, 1 << 20);
+    // Allocating what is already there leaves the file as it is
+    file.allocate(0, 10).unwrap();
+    assert_eq!(fs.stat("/file").unwrap().size, 1 << 20);
+    assert_eq!(fs.stats().bytes, 1 << 20);
+}
+
+#[test]
+fn out_of_memory() {
+    // Without a limit, a file too big for any allocation fails with ENOSPC instead of aborting
+    let fs = RamFS::new();
+    let mut file = RamFile::create(&fs, "/file").unwrap();
+    assert_eq!(errno(file.set_len(u64::MAX / 2)), libc::ENOSPC);
+    file.seek(SeekFrom::Start(i64::MAX as u64 - 1)).unwrap();
+    assert_eq!(errno(file.write(b"x")), libc::ENOSPC);
+    assert_eq!(fs.stat("/file").unwrap().size, 0);
+    assert!(fs.fsck().is_empty());
+}
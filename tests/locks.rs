@@ -0,0 +1,64 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use ramfs::{RamFile, RamFS};
+
+mod common;
+use common::errno;
+
+fn file() -> (RamFS, RamFile, RamFile) {
+    let fs = RamFS::new();
+    fs.write("/file", b"0123456789").unwrap();
+    let a = RamFile::open(&fs, "/file").unwrap();
+    let b = RamFile::open(&fs, "/file").unwrap();
+    (fs, a, b)
+}
+
+#[test]
+fn shared_and_exclusive() {
+    let (_fs, a, b) = file();
+    a.try_lock_shared().unwrap();
+    b.try_lock_shared().unwrap();
+    assert_eq!(errno(b.try_lock()), libc::EAGAIN);
+    b.unlock().unwrap();
+    a.try_lock().unwrap();
+    assert_eq!(errno(b.try_lock_shared()), libc::EAGAIN);
+    // flock and record locks never get in each other's way
+    b.try_lock_range(0, 0, true).unwrap();
+}
+
+#[test]
+fn ranges_split_and_merge() {
+    let (_fs, a, b) = file();
+    a.try_lock_range(0, 5, true).unwrap();
+    a.try_lock_range(5, 5, true).unwrap();
+    assert_eq!(errno(b.try_lock_range(4, 2, false)), libc::EAGAIN);
+    a.unlock_range(3, 4).unwrap();
+    b.try_lock_range(3, 4, true).unwrap();
+    assert_eq!(errno(b.try_lock_range(2, 1, false)), libc::EAGAIN);
+    assert_eq!(errno(b.try_lock_range(7, 1, false)), libc::EAGAIN);
+    // Downgrading the middle of a range leaves the rest exclusive
+    b.unlock_range(3, 4).unwrap();
+    a.try_lock_range(0, 10, true).unwrap();
+    a.try_lock_range(4, 2, false).unwrap();
+    b.try_lock_range(4, 2, false).unwrap();
+    assert_eq!(errno(b.try_lock_range(6, 1, false)), libc::EAGAIN);
+}
+
+#[test]
+fn blocking_wait() {
+    let (fs, a, b) = file();
+    a.lock().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let waiter = thread::spawn(move || {
+        b.lock().unwrap();
+        sender.send(()).unwrap();
+        b
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+    drop(a); // closing the file lets go of its lock
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    let b = waiter.join().unwrap();
+    assert_eq!(errno(RamFile::open(&fs, "/file").unwrap().try_lock_shared()), libc::EAGAIN);
+    drop(b);
+}
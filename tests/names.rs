@@ -0,0 +1,55 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use ramfs::RamFS;
+
+mod common;
+use common::errno;
+
+fn path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[test]
+fn non_utf8_names() {
+    let fs = RamFS::new();
+    let latin1 = path(b"/caf\xe9");
+    fs.mkdir(&latin1).unwrap();
+    fs.write(latin1.join(OsStr::from_bytes(b"\xff\xfe")), b"data").unwrap();
+    assert_eq!(fs.read(latin1.join(OsStr::from_bytes(b"\xff\xfe"))).unwrap(), b"data");
+
+    let names: Vec<OsString> = fs.readdir(&latin1).unwrap().into_iter().map(|entry| entry.name).collect();
+    assert_eq!(names, vec![OsString::from_vec(b"\xff\xfe".to_vec())]);
+
+    let ino = fs.stat(latin1.join(OsStr::from_bytes(b"\xff\xfe"))).unwrap().ino;
+    assert_eq!(fs.paths(ino), vec![path(b"/caf\xe9/\xff\xfe")]);
+
+    fs.rename(latin1.join(OsStr::from_bytes(b"\xff\xfe")), path(b"/\x80")).unwrap();
+    assert_eq!(fs.read(path(b"/\x80")).unwrap(), b"data");
+    fs.remove(path(b"/\x80")).unwrap();
+    fs.remove(&latin1).unwrap();
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn name_max() {
+    let fs = RamFS::new();
+    let longest = format!("/{}", "a".repeat(255));
+    let too_long = format!("/{}", "a".repeat(256));
+    fs.write(&longest, b"").unwrap();
+    assert_eq!(errno(fs.create(&too_long)), libc::ENAMETOOLONG);
+    assert_eq!(errno(fs.mkdir(&too_long)), libc::ENAMETOOLONG);
+    assert_eq!(errno(fs.stat(&too_long)), libc::ENAMETOOLONG);
+    assert_eq!(errno(fs.rename(&longest, &too_long)), libc::ENAMETOOLONG);
+    assert!(fs.stat(&longest).is_ok());
+}
+
+#[test]
+fn nul_in_name() {
+    let fs = RamFS::new();
+    assert_eq!(errno(fs.create(Path::new("/a\0b"))), libc::EINVAL);
+    assert_eq!(errno(fs.mkdir(Path::new("/a\0b"))), libc::EINVAL);
+    assert!(fs.readdir("/").unwrap().is_empty());
+    // Nothing got in under the part before the NUL either
+    assert_eq!(errno(fs.stat("/a")), libc::ENOENT);
+}
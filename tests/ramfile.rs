@@ -0,0 +1,69 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use ramfs::{RamFile, RamFS};
+
+mod common;
+use common::errno;
+
+#[test]
+fn read_and_seek() {
+    let fs = RamFS::new();
+    fs.write("/file", b"0123456789").unwrap();
+    let mut file = RamFile::open(&fs, "/file").unwrap();
+    let mut buf = [0; 4];
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"0123");
+    assert_eq!(file.seek(SeekFrom::Current(2)).unwrap(), 6);
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"6789");
+    assert_eq!(file.read(&mut buf).unwrap(), 0); // at the end
+    assert_eq!(file.seek(SeekFrom::End(-3)).unwrap(), 7);
+    let mut rest = String::new();
+    file.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "789");
+    assert_eq!(file.seek(SeekFrom::Start(1)).unwrap(), 1);
+    assert_eq!(file.stream_position().unwrap(), 1);
+    assert_eq!(file.seek(SeekFrom::Current(-2)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    // Reading from past the end finds nothing, and the position stays where it was put
+    assert_eq!(file.seek(SeekFrom::Start(100)).unwrap(), 100);
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn write_and_seek() {
+    let fs = RamFS::new();
+    let mut file = RamFile::create(&fs, "/file").unwrap();
+    file.write_all(b"hello world").unwrap();
+    file.seek(SeekFrom::Start(6)).unwrap();
+    file.write_all(b"there").unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"hello there");
+    // Writing past the end leaves a hole of zeros
+    file.seek(SeekFrom::End(2)).unwrap();
+    file.write_all(b"!").unwrap();
+    file.flush().unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"hello there\0\0!");
+    assert_eq!(file.metadata().unwrap().size, 14);
+}
+
+#[test]
+fn create_truncates() {
+    let fs = RamFS::new();
+    fs.write("/file", b"old contents").unwrap();
+    let mut file = RamFile::create(&fs, "/file").unwrap();
+    file.write_all(b"new").unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"new");
+    assert_eq!(errno(RamFile::create_new(&fs, "/file").map(drop)), libc::EEXIST);
+    assert_eq!(errno(RamFile::open(&fs, "/missing").map(drop)), libc::ENOENT);
+    fs.mkdir("/dir").unwrap();
+    assert_eq!(errno(RamFile::open(&fs, "/dir").map(drop)), libc::EISDIR);
+}
+
+#[test]
+fn io_copy() {
+    // Code written against std::io works unchanged
+    let fs = RamFS::new();
+    fs.write("/from", &[7; 100_000]).unwrap();
+    let mut from = RamFile::open(&fs, "/from").unwrap();
+    let mut to = RamFile::create(&fs, "/to").unwrap();
+    assert_eq!(io::copy(&mut from, &mut to).unwrap(), 100_000);
+    assert_eq!(fs.read("/to").unwrap(), fs.read("/from").unwrap());
+}
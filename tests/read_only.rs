@@ -0,0 +1,72 @@
+use std::io::Write;
+use ramfs::{Config, RamFile, RamFS, APPEND_ONLY, IMMUTABLE};
+
+mod common;
+use common::errno;
+
+fn tree(fs: &RamFS) {
+    fs.mkdir("/dir").unwrap();
+    fs.mkdir("/empty").unwrap();
+    fs.write("/dir/file", b"data").unwrap();
+}
+
+/* Every kind of change fails with EROFS and leaves the tree as it was */
+fn refuses_changes(fs: &RamFS, file: &mut RamFile) {
+    assert!(fs.is_read_only());
+    assert_eq!(errno(fs.write("/dir/file", b"new")), libc::EROFS);
+    assert_eq!(errno(file.write(b"new")), libc::EROFS);
+    assert_eq!(errno(fs.create("/dir/new")), libc::EROFS);
+    assert_eq!(errno(fs.mkdir("/dir/sub")), libc::EROFS);
+    assert_eq!(errno(fs.remove_file("/dir/file")), libc::EROFS);
+    assert_eq!(errno(fs.remove_dir("/empty")), libc::EROFS);
+    assert_eq!(errno(fs.rename("/dir/file", "/file")), libc::EROFS);
+    assert_eq!(errno(fs.hard_link("/dir/file", "/link")), libc::EROFS);
+    assert_eq!(errno(file.set_len(0)), libc::EROFS);
+    assert_eq!(errno(fs.set_flags("/dir/file", IMMUTABLE, 0)), libc::EROFS);
+    // Reading goes on as usual
+    assert_eq!(fs.read("/dir/file").unwrap(), b"data");
+    assert_eq!(fs.readdir("/dir").unwrap().len(), 1);
+    assert!(fs.stat("/empty").is_ok());
+}
+
+/* Once writable again, all of the above goes through */
+fn allows_changes(fs: &RamFS, file: &mut RamFile) {
+    assert!(!fs.is_read_only());
+    file.write_all(b"DA").unwrap();
+    file.set_len(3).unwrap();
+    fs.create("/dir/new").unwrap();
+    fs.mkdir("/dir/sub").unwrap();
+    fs.hard_link("/dir/file", "/link").unwrap();
+    fs.rename("/dir/file", "/file").unwrap();
+    fs.remove_file("/dir/new").unwrap();
+    fs.remove_dir("/empty").unwrap();
+    fs.set_flags("/file", APPEND_ONLY, 0).unwrap();
+    assert_eq!(fs.read("/link").unwrap(), b"DAt");
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn switched_at_runtime() {
+    let fs = RamFS::new();
+    tree(&fs);
+    let mut file = RamFile::open(&fs, "/dir/file").unwrap();
+    fs.set_read_only(true);
+    refuses_changes(&fs, &mut file);
+    fs.set_read_only(false);
+    allows_changes(&fs, &mut file);
+}
+
+#[test]
+fn mounted_read_only() {
+    // Like with -o ro, nothing can be added until the file-system is switched to read-write
+    let fs = RamFS::with_config(Config { read_only: true, ..Config::default() });
+    assert_eq!(errno(fs.mkdir("/dir")), libc::EROFS);
+    assert!(fs.readdir("/").unwrap().is_empty());
+    fs.set_read_only(false);
+    tree(&fs);
+    let mut file = RamFile::open(&fs, "/dir/file").unwrap();
+    fs.set_read_only(true);
+    refuses_changes(&fs, &mut file);
+    fs.set_read_only(false);
+    allows_changes(&fs, &mut file);
+}
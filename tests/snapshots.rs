@@ -0,0 +1,94 @@
+use ramfs::RamFS;
+
+mod common;
+use common::errno;
+
+#[test]
+fn snapshots_are_isolated() {
+    let fs = RamFS::new();
+    fs.mkdir("/dir").unwrap();
+    fs.write("/dir/file", b"before").unwrap();
+    fs.snapshot("snap").unwrap();
+
+    fs.write("/dir/file", b"after").unwrap();
+    fs.write("/dir/new", b"new").unwrap();
+    fs.remove_file("/dir/file").unwrap();
+    assert_eq!(fs.read("/.snapshots/snap/dir/file").unwrap(), b"before");
+    assert_eq!(errno(fs.stat("/.snapshots/snap/dir/new")), libc::ENOENT);
+    // Nothing under a snapshot can be changed
+    assert_eq!(errno(fs.write("/.snapshots/snap/dir/file", b"x")), libc::EROFS);
+    assert_eq!(errno(fs.remove_file("/.snapshots/snap/dir/file")), libc::EROFS);
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn restore_and_remove() {
+    let fs = RamFS::new();
+    fs.write("/file", b"one").unwrap();
+    fs.snapshot("one").unwrap();
+    fs.write("/file", b"two").unwrap();
+    fs.write("/other", b"").unwrap();
+
+    fs.restore_snapshot("one").unwrap();
+    assert_eq!(fs.read("/file").unwrap(), b"one");
+    assert_eq!(errno(fs.stat("/other")), libc::ENOENT);
+    // Inode numbers handed out before the restore are never reused
+    assert!(fs.create("/third").unwrap().ino > 3);
+
+    fs.remove_snapshot("one").unwrap();
+    assert_eq!(errno(fs.stat("/.snapshots/one")), libc::ENOENT);
+    assert_eq!(errno(fs.restore_snapshot("one")), libc::ENOENT);
+    assert_eq!(fs.read("/file").unwrap(), b"one");
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn snapshot_names() {
+    let fs = RamFS::new();
+    fs.snapshot("snap").unwrap();
+    assert_eq!(errno(fs.snapshot("snap")), libc::EEXIST);
+    for name in ["", ".", "..", "a/b", "a\0b"] {
+        assert_eq!(errno(fs.snapshot(name)), libc::EINVAL, "{:?}", name);
+    }
+    assert_eq!(errno(fs.snapshot(&"x".repeat(256))), libc::ENAMETOOLONG);
+    assert_eq!(fs.snapshot_names(), ["snap"]);
+}
+
+#[test]
+fn snapshot_dir_name_is_taken() {
+    let fs = RamFS::new();
+    fs.write("/file", b"data").unwrap();
+    fs.mkdir("/dir").unwrap();
+    // Renaming over it would replace it, which is as much a change to it as any other
+    assert_eq!(errno(fs.rename("/file", "/.snapshots")), libc::EROFS);
+    assert_eq!(errno(fs.rename("/dir", "/.snapshots")), libc::EROFS);
+    assert_eq!(errno(fs.create("/.snapshots")), libc::EEXIST);
+    assert_eq!(errno(fs.mkdir("/.snapshots")), libc::EEXIST);
+    assert_eq!(fs.read("/file").unwrap(), b"data");
+    assert!(fs.readdir("/").unwrap().iter().all(|entry| entry.name != ".snapshots"));
+}
+
+#[test]
+fn snapshot_dir_is_read_only() {
+    let fs = RamFS::new();
+    fs.mkdir("/dir").unwrap();
+    fs.snapshot("snap").unwrap();
+    assert_eq!(errno(fs.remove_dir("/.snapshots")), libc::EROFS);
+    assert_eq!(errno(fs.remove_file("/.snapshots")), libc::EROFS);
+    assert_eq!(errno(fs.rename("/.snapshots", "/elsewhere")), libc::EROFS);
+    assert_eq!(errno(fs.rename("/.snapshots", "/dir/.snapshots")), libc::EROFS);
+    assert_eq!(fs.snapshot_names(), ["snap"]);
+    assert!(fs.stat("/.snapshots/snap/dir").is_ok());
+    assert!(fs.fsck().is_empty());
+}
+
+#[test]
+fn no_restore_when_read_only() {
+    let fs = RamFS::new();
+    fs.write("/file", b"one").unwrap();
+    fs.snapshot("one").unwrap();
+    fs.write("/file", b"two").unwrap();
+    fs.set_read_only(true);
+    assert_eq!(errno(fs.restore_snapshot("one")), libc::EROFS);
+    assert_eq!(fs.read("/file").unwrap(), b"two");
+}